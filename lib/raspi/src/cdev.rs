@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use gpio_cdev::{self, Chip, EventRequestFlags, Line, LineEventHandle,
+                LineHandle, LineRequestFlags};
+use libc;
+
+use gpio::{poll_level, Direction, Pin};
+
+// Shown as the consumer of the requested lines, e.g. by gpioinfo
+const CONSUMER: &'static str = "unipager";
+
+fn error(err: gpio_cdev::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// GPIO chip accessed through the character device `/dev/gpiochipN`.
+pub struct CdevGpio {
+    chip: Mutex<Chip>,
+    pin_mapping: Option<Vec<usize>>,
+    lines: HashMap<usize, String>,
+}
+
+impl CdevGpio {
+    /// Open a chip by its path, name (e.g. `gpiochip0`) or label. Pins
+    /// listed in `lines` are looked up by the line name, all others are
+    /// used as line offsets after applying the pin mapping.
+    pub fn new(
+        chip: &str,
+        pin_mapping: Option<Vec<usize>>,
+        lines: HashMap<usize, String>,
+    ) -> io::Result<CdevGpio> {
+        Ok(CdevGpio {
+            chip: Mutex::new(open_chip(chip)?),
+            pin_mapping,
+            lines,
+        })
+    }
+
+    pub fn pin(&self, number: usize, direction: Direction) -> io::Result<CdevGpioPin> {
+        let mut chip = self.chip.lock().unwrap();
+
+        let offset = match line_id(number, &self.pin_mapping, &self.lines) {
+            LineId::Name(name) => find_line(&chip, name)?,
+            LineId::Offset(offset) => offset,
+        };
+
+        let line = chip.get_line(offset).map_err(|err| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("GPIO line {} of {}: {}", offset, chip.name(), err)
+        ))?;
+
+        CdevGpioPin::new(line, direction)
+    }
+}
+
+// A line is either selected by its name or by its offset on the chip.
+#[derive(Debug, PartialEq)]
+enum LineId<'a> {
+    Name(&'a str),
+    Offset(u32),
+}
+
+fn line_id<'a>(
+    number: usize,
+    pin_mapping: &Option<Vec<usize>>,
+    lines: &'a HashMap<usize, String>,
+) -> LineId<'a> {
+    if let Some(name) = lines.get(&number) {
+        return LineId::Name(name);
+    }
+
+    let offset = pin_mapping.as_ref().and_then(|mapping| {
+        mapping.get(number).map(|num| *num)
+    }).unwrap_or(number);
+    LineId::Offset(offset as u32)
+}
+
+// Offset of the first line with the given name.
+fn line_offset<I>(names: I, name: &str) -> Option<u32>
+    where I: IntoIterator<Item = Option<String>>
+{
+    names.into_iter()
+        .position(|line| line.as_ref().map(String::as_str) == Some(name))
+        .map(|offset| offset as u32)
+}
+
+fn open_chip(name: &str) -> io::Result<Chip> {
+    if name.starts_with('/') {
+        return Chip::new(name).map_err(error);
+    }
+
+    for chip in gpio_cdev::chips().map_err(error)? {
+        let chip = chip.map_err(error)?;
+        if chip.name() == name || chip.label() == name {
+            return Ok(chip);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("GPIO chip {} not found", name),
+    ))
+}
+
+fn find_line(chip: &Chip, name: &str) -> io::Result<u32> {
+    let names = chip.lines().map(|line| {
+        line.info().ok().and_then(|info| info.name().map(String::from))
+    });
+
+    line_offset(names, name).ok_or_else(|| io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("GPIO line {} not found on {}", name, chip.name()),
+    ))
+}
+
+enum Handle {
+    Output(LineHandle),
+    Input(LineHandle),
+    // Input that also reports rising and falling edges
+    Events(LineEventHandle),
+}
+
+/// The line is released on drop and keeps its last output level, so users
+/// have to set the inactive level themselves.
+pub struct CdevGpioPin {
+    line: Line,
+    direction: Direction,
+    // Released while the direction changes
+    handle: Option<Handle>,
+}
+
+impl CdevGpioPin {
+    pub fn new(line: Line, direction: Direction) -> io::Result<CdevGpioPin> {
+        let handle = request(&line, direction)?;
+        Ok(CdevGpioPin {
+            line,
+            direction,
+            handle: Some(handle),
+        })
+    }
+}
+
+fn request(line: &Line, direction: Direction) -> io::Result<Handle> {
+    let error = |err: gpio_cdev::Error| io::Error::new(
+        io::ErrorKind::Other,
+        format!("Failed to request GPIO line {}: {}", line.offset(), err)
+    );
+
+    match direction {
+        Direction::Output => line
+            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)
+            .map(Handle::Output)
+            .map_err(error),
+        // Not every chip supports edge events.
+        Direction::Input => match line.events(
+            LineRequestFlags::INPUT,
+            EventRequestFlags::BOTH_EDGES,
+            CONSUMER,
+        ) {
+            Ok(handle) => Ok(Handle::Events(handle)),
+            Err(_) => line
+                .request(LineRequestFlags::INPUT, 0, CONSUMER)
+                .map(Handle::Input)
+                .map_err(error),
+        },
+    }
+}
+
+// Wait until the file descriptor is readable. Returns false on timeout.
+fn poll(fd: i32, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN | libc::POLLPRI,
+        revents: 0,
+    };
+
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+    match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+impl Pin for CdevGpioPin {
+    // The line has to be released before it can be requested again. If the
+    // new direction is refused, the line is requested as before.
+    fn set_direction(&mut self, direction: Direction) {
+        self.handle = None;
+
+        match request(&self.line, direction) {
+            Ok(handle) => {
+                self.handle = Some(handle);
+                self.direction = direction;
+            }
+            Err(err) => {
+                error!("Failed to set the GPIO direction: {}", err);
+                self.handle = request(&self.line, self.direction).ok();
+            }
+        }
+    }
+
+    fn set(&self, value: bool) {
+        assert_eq!(self.direction, Direction::Output);
+        if let Some(Handle::Output(ref handle)) = self.handle {
+            handle.set_value(value as u8).ok();
+        }
+    }
+
+    fn read(&self) -> bool {
+        assert_eq!(self.direction, Direction::Input);
+        let value = match self.handle {
+            Some(Handle::Input(ref handle)) => handle.get_value(),
+            Some(Handle::Events(ref handle)) => handle.get_value(),
+            _ => return false,
+        };
+        value.map(|val| val != 0).unwrap_or(false)
+    }
+
+    fn wait_for(&mut self, value: bool, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            if self.read() == value {
+                return true;
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return false;
+            }
+
+            let handle = match self.handle {
+                Some(Handle::Events(ref mut handle)) => handle,
+                _ => return poll_level(self, value, timeout - elapsed),
+            };
+
+            match poll(handle.as_raw_fd(), timeout - elapsed) {
+                // Consume the event, the level is checked again anyway.
+                Ok(true) => { handle.get_event().ok(); }
+                Ok(false) => return false,
+                Err(_) => return poll_level(self, value, timeout - elapsed),
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_line_id() {
+    let mapping = Some(vec![17, 18, 27]);
+    let mut lines = HashMap::new();
+    lines.insert(1, String::from("PA6"));
+
+    assert_eq!(line_id(0, &mapping, &lines), LineId::Offset(17));
+    assert_eq!(line_id(1, &mapping, &lines), LineId::Name("PA6"));
+    // Pins outside of the mapping are used as offsets.
+    assert_eq!(line_id(5, &mapping, &lines), LineId::Offset(5));
+    assert_eq!(line_id(2, &None, &lines), LineId::Offset(2));
+}
+
+#[test]
+pub fn test_line_offset() {
+    let names = || vec![
+        Some(String::from("ID_SDA")),
+        None,
+        Some(String::from("GPIO17")),
+        Some(String::from("GPIO17")),
+    ];
+
+    assert_eq!(line_offset(names(), "GPIO17"), Some(2));
+    assert_eq!(line_offset(names(), "ID_SDA"), Some(0));
+    assert_eq!(line_offset(names(), "GPIO18"), None);
+}
@@ -1,7 +1,16 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
 use std::ptr::{read_volatile, write_volatile};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cdev::CdevGpio;
 
 use libc;
+#[cfg(feature = "mock")]
+use mock::MockGpio;
 use model::Model;
 use sysfs_gpio;
 
@@ -15,18 +24,44 @@ pub enum Gpio {
     SysFsGpio {
         pin_mapping: Option<Vec<usize>>
     },
+    CdevGpio(CdevGpio),
+    #[cfg(feature = "mock")]
+    Mock(MockGpio),
 }
 
 impl Gpio {
+    /// Memory mapped GPIO on known models, otherwise the sysfs interface or,
+    /// if the kernel has none, the first GPIO character device.
     pub fn new() -> Option<Gpio> {
         let model = Model::get();
-        let base = model.gpio_base();
 
-        if base.is_none() {
-            return Some(Gpio::SysFsGpio {
-                pin_mapping: model.pin_mapping()
-            });
+        if model.gpio_base().is_some() {
+            return Gpio::memory();
+        }
+
+        if Path::new("/sys/class/gpio").exists() {
+            return Some(Gpio::sysfs());
+        }
+
+        Gpio::cdev("gpiochip0", HashMap::new()).ok()
+    }
+
+    pub fn sysfs() -> Gpio {
+        Gpio::SysFsGpio {
+            pin_mapping: Model::get().pin_mapping()
         }
+    }
+
+    /// GPIO character device, see `CdevGpio::new`.
+    pub fn cdev(chip: &str, lines: HashMap<usize, String>) -> io::Result<Gpio> {
+        let pin_mapping = Model::get().pin_mapping();
+        Ok(Gpio::CdevGpio(CdevGpio::new(chip, pin_mapping, lines)?))
+    }
+
+    /// Registers mapped from `/dev/mem`, only available on known models.
+    pub fn memory() -> Option<Gpio> {
+        let model = Model::get();
+        let base = model.gpio_base()?;
 
         let mapped_base = unsafe {
             let mem = "/dev/mem\0".as_ptr() as *const libc::c_char;
@@ -41,7 +76,7 @@ impl Gpio {
                 libc::PROT_READ | libc::PROT_WRITE,
                 libc::MAP_SHARED,
                 mem_fd,
-                base.unwrap() as libc::off_t,
+                base as libc::off_t,
             );
 
             libc::close(mem_fd);
@@ -59,24 +94,34 @@ impl Gpio {
         })
     }
 
-    pub fn pin(&self, number: usize, direction: Direction) -> Box<dyn Pin> {
+    pub fn pin(&self, number: usize, direction: Direction) -> io::Result<Box<dyn Pin>> {
         match self {
             &Gpio::MemGpio { ref base, ref pin_mapping } => {
                 let number = pin_mapping.as_ref().and_then(|mapping| {
                     mapping.get(number).map(|num| *num)
                 }).unwrap_or(number);
-                Box::new(MemGpioPin::new(base.clone(), number, direction))
+                Ok(Box::new(MemGpioPin::new(base.clone(), number, direction)))
             }
             &Gpio::SysFsGpio { ref pin_mapping } => {
                 let number = pin_mapping.as_ref().and_then(|mapping| {
                     mapping.get(number).map(|num| *num)
                 }).unwrap_or(number);
-                Box::new(SysFsGpioPin::new(number, direction))
+                Ok(Box::new(SysFsGpioPin::new(number, direction)?))
             }
+            &Gpio::CdevGpio(ref gpio) => {
+                Ok(Box::new(gpio.pin(number, direction)?))
+            }
+            #[cfg(feature = "mock")]
+            &Gpio::Mock(ref mock) => Ok(mock.pin(number, direction)),
         }
     }
 }
 
+// The registers are only written with single volatile stores, so the
+// mapping can be shared between threads.
+unsafe impl Send for GpioBase {}
+unsafe impl Sync for GpioBase {}
+
 impl Drop for GpioBase {
     fn drop(&mut self) {
         unsafe { libc::munmap(self.0 as *mut libc::c_void, 0x1000) };
@@ -89,7 +134,9 @@ pub enum Direction {
     Output,
 }
 
-pub trait Pin {
+/// Pins can be moved to other threads, e.g. to release a PTT while the
+/// thread driving the transmitter hangs.
+pub trait Pin: Send {
     fn set_direction(&mut self, direction: Direction);
     fn set(&self, value: bool);
     fn read(&self) -> bool;
@@ -101,6 +148,26 @@ pub trait Pin {
     fn set_low(&self) {
         self.set(false);
     }
+
+    /// Wait until the input has the given level, at most for the timeout.
+    /// Returns whether the level was reached. Pins with edge events are not
+    /// polled.
+    fn wait_for(&mut self, value: bool, timeout: Duration) -> bool {
+        poll_level(self, value, timeout)
+    }
+}
+
+pub fn poll_level<P: Pin + ?Sized>(pin: &P, value: bool, timeout: Duration) -> bool {
+    let start = Instant::now();
+
+    while pin.read() != value {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    true
 }
 
 pub struct SysFsGpioPin {
@@ -109,28 +176,35 @@ pub struct SysFsGpioPin {
 }
 
 impl SysFsGpioPin {
-    pub fn new(number: usize, direction: Direction) -> SysFsGpioPin {
-        let mut pin = SysFsGpioPin {
+    pub fn new(number: usize, direction: Direction) -> io::Result<SysFsGpioPin> {
+        let pin = SysFsGpioPin {
             pin: sysfs_gpio::Pin::new(number as u64),
             direction,
         };
 
-        pin.pin.export().expect("Failed to export GPIO pin.");
-        pin.set_direction(direction);
-        pin
+        let error = |err: sysfs_gpio::Error| io::Error::new(
+            io::ErrorKind::Other,
+            format!("Failed to set up GPIO pin {}: {}", number, err)
+        );
+
+        pin.pin.export().map_err(error)?;
+        pin.pin.set_direction(sysfs_direction(direction)).map_err(error)?;
+        Ok(pin)
+    }
+}
+
+fn sysfs_direction(direction: Direction) -> sysfs_gpio::Direction {
+    match direction {
+        Direction::Input => sysfs_gpio::Direction::In,
+        Direction::Output => sysfs_gpio::Direction::Out
     }
 }
 
 impl Pin for SysFsGpioPin {
     fn set_direction(&mut self, direction: Direction) {
         self.direction = direction;
-
-        let direction = match self.direction {
-            Direction::Input => sysfs_gpio::Direction::In,
-            Direction::Output => sysfs_gpio::Direction::Out
-        };
-
-        self.pin.set_direction(direction).expect("Failed to set GPIO direction.");
+        self.pin.set_direction(sysfs_direction(direction))
+            .expect("Failed to set GPIO direction.");
     }
 
     fn set(&self, value: bool) {
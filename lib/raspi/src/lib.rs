@@ -1,8 +1,17 @@
+extern crate gpio_cdev;
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate sysfs_gpio;
 
+pub mod cdev;
 pub mod gpio;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod model;
 
+pub use self::cdev::{CdevGpio, CdevGpioPin};
 pub use self::gpio::{Gpio, Pin, Direction};
+#[cfg(feature = "mock")]
+pub use self::mock::MockGpio;
 pub use self::model::Model;
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use gpio::{Direction, Pin};
+
+#[derive(Default)]
+struct State {
+    // Every value set on an output pin, in order
+    transitions: Vec<(usize, bool)>,
+    // Values returned by the next reads of an input pin
+    scripts: HashMap<usize, VecDeque<bool>>,
+    // Value of an input pin once its script is exhausted
+    levels: HashMap<usize, bool>,
+}
+
+/// Simulated GPIO that records the output pins and plays back scripted
+/// inputs, for testing the drivers without hardware.
+#[derive(Clone, Default)]
+pub struct MockGpio {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockGpio {
+    pub fn new() -> MockGpio {
+        MockGpio::default()
+    }
+
+    pub fn pin(&self, number: usize, direction: Direction) -> Box<dyn Pin> {
+        Box::new(MockPin {
+            gpio: self.clone(),
+            number,
+            direction,
+        })
+    }
+
+    /// Set the value read from an input pin.
+    pub fn set_input(&self, number: usize, value: bool) {
+        self.state.lock().unwrap().levels.insert(number, value);
+    }
+
+    /// Values returned by the next reads of an input pin before it falls back
+    /// to the value set with `set_input`.
+    pub fn script(&self, number: usize, values: &[bool]) {
+        let mut state = self.state.lock().unwrap();
+        state.scripts.entry(number).or_insert_with(VecDeque::new).extend(values);
+    }
+
+    /// All values set on output pins as (pin, value).
+    pub fn transitions(&self) -> Vec<(usize, bool)> {
+        self.state.lock().unwrap().transitions.clone()
+    }
+
+    /// Values set on a single output pin.
+    pub fn transitions_of(&self, number: usize) -> Vec<bool> {
+        self.transitions()
+            .into_iter()
+            .filter(|&(pin, _)| pin == number)
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().transitions.clear();
+    }
+}
+
+pub struct MockPin {
+    gpio: MockGpio,
+    number: usize,
+    direction: Direction,
+}
+
+impl Pin for MockPin {
+    fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    fn set(&self, value: bool) {
+        assert_eq!(self.direction, Direction::Output);
+        let mut state = self.gpio.state.lock().unwrap();
+        state.transitions.push((self.number, value));
+    }
+
+    fn read(&self) -> bool {
+        assert_eq!(self.direction, Direction::Input);
+        let mut state = self.gpio.state.lock().unwrap();
+
+        let scripted = state.scripts
+            .get_mut(&self.number)
+            .and_then(|script| script.pop_front());
+
+        scripted.unwrap_or_else(|| {
+            state.levels.get(&self.number).cloned().unwrap_or(false)
+        })
+    }
+}
@@ -33,6 +33,10 @@ impl Model {
             return Model::Unknown;
         }
 
+        Model::from_cpuinfo(&cpuinfo)
+    }
+
+    pub fn from_cpuinfo(cpuinfo: &str) -> Model {
         let revision = cpuinfo.split('\n')
             .filter(|line| line.starts_with("Revision")).next()
             .and_then(|line| line.split(':').nth(1))
@@ -0,0 +1,193 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::config::{self, Config, Format};
+use crate::frontend::password;
+use crate::message::{Message, MessageProvider};
+use crate::pocsag::{self, Generator, MessageType, TestGenerator};
+use crate::transmitter;
+
+#[derive(Parser, Debug)]
+#[clap(name = "unipager", version, about = "POCSAG transmitter controller")]
+pub struct Args {
+    /// Config file, relative to the data directory. Files ending in .toml
+    /// are read as TOML, all others as JSON.
+    #[clap(long, value_name = "FILE", default_value = "config.json")]
+    pub config: PathBuf,
+
+    /// Password file, relative to the data directory
+    #[clap(long, value_name = "FILE", default_value = "password")]
+    pub password_file: PathBuf,
+
+    /// Directory for the config, password, queue and certificate files
+    /// [default: current directory]
+    #[clap(long, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Check the config file and exit
+    #[clap(long)]
+    pub check_config: bool,
+
+    /// Print the default config and exit
+    #[clap(long)]
+    pub print_default_config: bool,
+
+    /// Print the config including environment overrides, with secrets
+    /// redacted, and exit
+    #[clap(long)]
+    pub print_config: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Send a message once through the configured transmitter, ignoring the
+    /// timeslots
+    Send {
+        /// Address of the pager
+        ric: u32,
+        message: String,
+        /// Function bits
+        #[clap(long, default_value = "3")]
+        func: u8,
+        /// Send a numeric instead of an alphanumeric message
+        #[clap(long)]
+        numeric: bool,
+        /// Baud rate
+        #[clap(long, default_value = "1200")]
+        speed: u32
+    },
+    /// Transmit the test pattern
+    Test
+}
+
+/// Change to the data directory and set the file locations.
+pub fn apply_paths(args: &Args) -> io::Result<()> {
+    if let Some(ref dir) = args.data_dir {
+        env::set_current_dir(dir)?;
+    }
+
+    config::set_path(args.config.clone());
+    password::set_path(args.password_file.clone());
+    Ok(())
+}
+
+// Print in the format of the config file.
+fn print(config: &Config) {
+    let format = Format::from_path(&config::path());
+    println!("{}", format.serialize(config).unwrap());
+}
+
+pub fn print_default_config() {
+    print(&Config::default());
+}
+
+/// Returns the exit code.
+pub fn print_config() -> i32 {
+    let path = config::path();
+
+    match Config::read(&path) {
+        Ok(config) => {
+            print(&config.redacted());
+            0
+        }
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            1
+        }
+    }
+}
+
+// Read and validate the config file, printing the errors.
+fn read_valid() -> Option<Config> {
+    let path = config::path();
+
+    match Config::read(&path) {
+        Ok(config) => match config.validate() {
+            Ok(()) => Some(config),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}: {}", path.display(), error);
+                }
+                None
+            }
+        },
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Returns the exit code.
+pub fn check_config() -> i32 {
+    match read_valid() {
+        Some(_) => {
+            println!("{}: OK", config::path().display());
+            0
+        }
+        None => 1
+    }
+}
+
+// Ends the transmission after the first message.
+struct SingleMessage;
+
+impl MessageProvider for SingleMessage {
+    fn next(&mut self, _: usize) -> Option<Message> {
+        None
+    }
+}
+
+/// Returns the exit code. The config file has to be valid, so that nothing
+/// is sent with the defaults instead.
+pub fn run(command: Command) -> i32 {
+    let config = match read_valid() {
+        Some(config) => config,
+        None => return 1
+    };
+    let mut transmitter = match transmitter::from_config(&config) {
+        Ok(transmitter) => transmitter,
+        Err(err) => {
+            error!("Failed to initialize the transmitter: {}", err);
+            return 1;
+        }
+    };
+
+    let result = match command {
+        Command::Send { ric, message, func, numeric, speed } => {
+            let message = pocsag::Message {
+                mtype: if numeric {
+                    MessageType::Numeric
+                } else {
+                    MessageType::AlphaNum
+                },
+                speed,
+                ric,
+                func,
+                data: message
+            };
+
+            info!("Sending {:?}", message);
+            transmitter.send(&mut Generator::new(&mut SingleMessage, message))
+        }
+        Command::Test => {
+            info!("Transmitting test pattern...");
+            transmitter.send(&mut TestGenerator::new(1125))
+        }
+    };
+    transmitter.shutdown();
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            error!("Transmission failed: {}", err);
+            1
+        }
+    }
+}
@@ -1,14 +1,61 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::fmt;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
-use serde_json;
+use chrono::{DateTime, Utc};
+use raspi::Model;
+use serde_json::{self, Value};
+
+use crate::pocsag::MessageType;
+use crate::queue::NUM_PRIORITIES;
+use crate::transmitter::raspager::pins::{RASPAGER1_PINS, RASPAGER2_PINS};
 
 const CONFIG_FILE: &'static str = "config.json";
+// Prefix of the environment variables that override config fields
+const ENV_PREFIX: &'static str = "UNIPAGER_";
+const REDACTED: &'static str = "<redacted>";
+
+// Version of the config format, increased whenever a migration is needed.
+pub const CONFIG_VERSION: u32 = 2;
+// Number of previous configs kept for rollbacks
+const HISTORY_SIZE: usize = 10;
+
+// Frequency range of the ADF7012 in Hz
+const ADF7012_FREQ_MIN: u32 = 75_000_000;
+const ADF7012_FREQ_MAX: u32 = 1_000_000_000;
+
+const BAUD_RATES: [usize; 10] = [
+    300, 600, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200
+];
+const AUDIO_BAUD_RATES: [usize; 3] = [512, 1200, 2400];
 
 lazy_static! {
-    pub static ref CONFIG: RwLock<Config> = RwLock::new(Config::load());
+    static ref CONFIG_PATH: RwLock<PathBuf> =
+        RwLock::new(PathBuf::from(CONFIG_FILE));
+    // Replaced by `init` at startup
+    pub static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+}
+
+/// Load the config file at startup, see `Config::load`.
+pub fn init() -> io::Result<()> {
+    *CONFIG.write().unwrap() = Config::load()?;
+    Ok(())
+}
+
+/// Set the location of the config file. Must be called before the config is
+/// accessed for the first time.
+pub fn set_path(path: PathBuf) {
+    *CONFIG_PATH.write().unwrap() = path;
+}
+
+pub fn path() -> PathBuf {
+    CONFIG_PATH.read().unwrap().clone()
 }
 
 fn default_fallback_servers() -> Vec<(String, u16)> {
@@ -24,6 +71,10 @@ fn default_fallback_servers() -> Vec<(String, u16)> {
 
 fn default_mod_deviation() -> u16 { 13 }
 
+fn default_func() -> u8 { 3 }
+
+fn default_message_type() -> MessageType { MessageType::AlphaNum }
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct C9000Config {
@@ -137,6 +188,50 @@ impl Default for PttConfig {
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum GpioBackend {
+    // Memory mapped on known models, otherwise sysfs or the character device
+    Auto,
+    Memory,
+    SysFs,
+    // Character device /dev/gpiochipN
+    Cdev,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GpioConfig {
+    pub backend: GpioBackend,
+    // Path, name or label of the character device
+    pub chip: String,
+    // Line names of pin numbers, e.g. "0": "GPIO17"
+    pub lines: BTreeMap<String, String>,
+}
+
+impl Default for GpioConfig {
+    fn default() -> GpioConfig {
+        GpioConfig {
+            backend: GpioBackend::Auto,
+            chip: String::from("gpiochip0"),
+            lines: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum MasterProtocol {
+    // DAPNET core with RabbitMQ
+    Core,
+    // Line based protocol of the legacy DAPNET/FunkrufMaster
+    FunkrufMaster,
+}
+
+impl Default for MasterProtocol {
+    fn default() -> MasterProtocol {
+        MasterProtocol::Core
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct MasterConfig {
@@ -144,8 +239,11 @@ pub struct MasterConfig {
     pub port: u16,
     pub call: String,
     pub auth: String,
+    // File to read the auth key from instead of storing it in the config.
+    pub auth_file: String,
     pub fallback: Vec<(String, u16)>,
     pub reconnect_timeout: u64,
+    pub protocol: MasterProtocol,
     // In standalone mode no connection to a server is attempated and the time slots are overwritten.
     pub standalone_mode: bool,
 }
@@ -157,13 +255,222 @@ impl Default for MasterConfig {
             port: 80,
             call: String::from(""),
             auth: String::from(""),
+            auth_file: String::from(""),
             fallback: default_fallback_servers(),
             reconnect_timeout: 30,
+            protocol: MasterProtocol::default(),
             standalone_mode: false,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub server: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: String,
+    pub password: String,
+    pub topics: Vec<String>,
+    pub qos: u8,
+    pub reconnect_timeout: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> MqttConfig {
+        MqttConfig {
+            enabled: false,
+            server: String::from("localhost"),
+            port: 1883,
+            client_id: String::from("unipager"),
+            username: String::from(""),
+            password: String::from(""),
+            topics: vec![String::from("unipager/messages")],
+            qos: 1,
+            reconnect_timeout: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TapPager {
+    pub id: String,
+    pub ric: u32,
+    #[serde(default = "default_func")]
+    pub func: u8,
+    #[serde(default = "default_message_type")]
+    #[serde(rename = "type")]
+    pub mtype: MessageType,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TapConfig {
+    pub enabled: bool,
+    // TCP address to listen on. Leave empty to disable the TCP listener.
+    pub listen: String,
+    // Serial device to accept pages on. Leave empty to disable.
+    pub serial_port: String,
+    pub baudrate: usize,
+    pub password: String,
+    pub priority: usize,
+    // Pager IDs that are not listed in `pagers` are used as RIC if numeric.
+    pub numeric_ids: bool,
+    pub func: u8,
+    pub pagers: Vec<TapPager>,
+}
+
+impl Default for TapConfig {
+    fn default() -> TapConfig {
+        TapConfig {
+            enabled: false,
+            listen: String::from("0.0.0.0:7001"),
+            serial_port: String::from(""),
+            baudrate: 1200,
+            password: String::from(""),
+            priority: 3,
+            numeric_ids: true,
+            func: default_func(),
+            pagers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SnppConfig {
+    pub enabled: bool,
+    pub listen: String,
+    pub priority: usize,
+}
+
+impl Default for SnppConfig {
+    fn default() -> SnppConfig {
+        SnppConfig {
+            enabled: false,
+            listen: String::from("0.0.0.0:444"),
+            priority: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NtpConfig {
+    // Maximum clock offset in milliseconds
+    pub max_offset: u64,
+    // Do not transmit if the clock is off. Otherwise only a warning is logged.
+    pub enforce: bool,
+}
+
+impl Default for NtpConfig {
+    fn default() -> NtpConfig {
+        NtpConfig {
+            max_offset: 100,
+            enforce: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    // Finish the current transmission instead of aborting it.
+    pub drain: bool,
+    // Seconds to wait for the transmission to finish before it is aborted
+    pub timeout: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> ShutdownConfig {
+        ShutdownConfig {
+            drain: false,
+            timeout: 30,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FrontendConfig {
+    // Addresses of the web interface, which also serves the websocket on /ws.
+    pub http: Vec<String>,
+    // Addresses of the standalone websocket server. May be empty.
+    pub websocket: Vec<String>,
+    // IPs of reverse proxies whose X-Forwarded-For header is trusted for the
+    // client address, e.g. to throttle failed logins.
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for FrontendConfig {
+    fn default() -> FrontendConfig {
+        FrontendConfig {
+            http: vec![String::from("0.0.0.0:8073")],
+            websocket: vec![String::from("0.0.0.0:8055")],
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TlsConfig {
+    // Serve the web interface and the websocket over TLS.
+    pub enabled: bool,
+    // PEM files, a self-signed certificate is created if both are missing.
+    pub cert: String,
+    pub key: String,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            enabled: false,
+            cert: String::from("unipager.crt"),
+            key: String::from("unipager.key"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    // May only send messages.
+    Send,
+    // May only read status, telemetry and the queue.
+    Read,
+    // Full access, same as the password.
+    Admin,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct RicRange {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl RicRange {
+    pub fn contains(&self, ric: u32) -> bool {
+        self.from <= ric && ric <= self.to
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub name: String,
+    // SHA-256 hash of the key, the key itself is never stored.
+    pub hash: String,
+    pub scope: ApiKeyScope,
+    // Maximum number of messages per minute, 0 means unlimited.
+    #[serde(default)]
+    pub rate_limit: u32,
+    // RICs this key may send to. An empty list allows all RICs.
+    #[serde(default)]
+    pub rics: Vec<RicRange>,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum Transmitter {
     Dummy,
@@ -194,9 +501,55 @@ impl fmt::Display for Transmitter {
     }
 }
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RoutingConfig {
+    // RICs to send to. An empty list allows all RICs.
+    pub rics: Vec<RicRange>,
+    // Fixed timeslots as hex digits, e.g. "0123". If empty, the timeslots
+    // assigned by the master are used.
+    pub timeslots: String,
+}
+
+impl RoutingConfig {
+    pub fn accepts(&self, ric: u32) -> bool {
+        self.rics.is_empty() || self.rics.iter().any(|range| range.contains(ric))
+    }
+}
+
+/// An additional transmitter, configured like the main one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TransmitterConfig {
+    pub name: String,
+    pub transmitter: Transmitter,
+    pub ptt: PttConfig,
+    pub raspager: RaspagerConfig,
+    pub c9000: C9000Config,
+    pub audio: AudioConfig,
+    pub rfm69: RFM69Config,
+    pub routing: RoutingConfig,
+}
+
+impl Default for TransmitterConfig {
+    fn default() -> TransmitterConfig {
+        TransmitterConfig {
+            name: String::from(""),
+            transmitter: Transmitter::default(),
+            ptt: PttConfig::default(),
+            raspager: RaspagerConfig::default(),
+            c9000: C9000Config::default(),
+            audio: AudioConfig::default(),
+            rfm69: RFM69Config::default(),
+            routing: RoutingConfig::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct Config {
+    pub version: u32,
     pub master: MasterConfig,
     pub transmitter: Transmitter,
     pub ptt: PttConfig,
@@ -204,52 +557,1073 @@ pub struct Config {
     pub c9000: C9000Config,
     pub audio: AudioConfig,
     pub rfm69: RFM69Config,
+    pub routing: RoutingConfig,
+    pub transmitters: Vec<TransmitterConfig>,
+    pub gpio: GpioConfig,
+    pub mqtt: MqttConfig,
+    pub tap: TapConfig,
+    pub snpp: SnppConfig,
+    pub ntp: NtpConfig,
+    pub shutdown: ShutdownConfig,
+    pub frontend: FrontendConfig,
+    pub tls: TlsConfig,
+    pub api_keys: Vec<ApiKey>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            master: MasterConfig::default(),
+            transmitter: Transmitter::default(),
+            ptt: PttConfig::default(),
+            raspager: RaspagerConfig::default(),
+            c9000: C9000Config::default(),
+            audio: AudioConfig::default(),
+            rfm69: RFM69Config::default(),
+            routing: RoutingConfig::default(),
+            transmitters: Vec::new(),
+            gpio: GpioConfig::default(),
+            mqtt: MqttConfig::default(),
+            tap: TapConfig::default(),
+            snpp: SnppConfig::default(),
+            ntp: NtpConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            frontend: FrontendConfig::default(),
+            tls: TlsConfig::default(),
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+// Version 0 configs had no version field, the format is unchanged.
+fn migrate_v0(_config: &mut Value) {}
+
+// Version 1 configs identified the additional transmitters by their position.
+// Their names are used in file names now and have to be unique.
+fn migrate_v1(config: &mut Value) {
+    let transmitters = match config.pointer_mut("/transmitters")
+        .and_then(Value::as_array_mut)
+    {
+        Some(transmitters) => transmitters,
+        None => return
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    for (i, transmitter) in transmitters.iter_mut().enumerate() {
+        let old = transmitter.get("name").and_then(Value::as_str).unwrap_or("");
+        let mut name: String = old.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }
+        }).collect();
+        if name.is_empty() || names.contains(&name) {
+            name = format!("{}{}", name, i + 1);
+        }
+
+        if name != old {
+            info!("Renaming transmitter {:?} to {}.", old, name);
+        }
+        if let Some(object) = transmitter.as_object_mut() {
+            object.insert("name".to_owned(), Value::from(name.clone()));
+        }
+        names.push(name);
+    }
+}
+
+// Migrations from each version to the next, applied to the raw JSON.
+const MIGRATIONS: [fn(&mut Value); CONFIG_VERSION as usize] = [
+    migrate_v0, migrate_v1
+];
+
+fn migrate(config: &mut Value) {
+    let version = config.get("version").and_then(Value::as_u64).unwrap_or(0);
+
+    if version > CONFIG_VERSION as u64 {
+        warn!("Config version {} is newer than supported version {}.",
+              version, CONFIG_VERSION);
+        return;
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating config from version {} to {}.", from, from + 1);
+        migration(config);
+    }
+
+    if let Some(object) = config.as_object_mut() {
+        object.insert("version".to_owned(), Value::from(CONFIG_VERSION));
+    }
+}
+
+/// Format of the config file, selected by the file extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Format::Toml,
+            _ => Format::Json
+        }
+    }
+
+    fn parse(self, data: &str) -> Result<Value, String> {
+        match self {
+            Format::Json => serde_json::from_str(data).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str(data).map_err(|e| e.to_string())
+        }
+    }
+
+    pub fn serialize<T: serde::Serialize>(self, value: &T) -> Result<String, String> {
+        match self {
+            Format::Json => {
+                serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+            }
+            Format::Toml => {
+                toml::to_string_pretty(value).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Config fields set by `UNIPAGER_*` environment variables, as JSON pointer
+/// and value. Nested fields are separated by two underscores, e.g.
+/// `UNIPAGER_MASTER__AUTH` sets `master.auth`.
+fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = env::vars()
+        .filter_map(|(name, value)| {
+            let field = name.strip_prefix(ENV_PREFIX)?;
+            let pointer = field
+                .split("__")
+                .map(|part| format!("/{}", part.to_lowercase()))
+                .collect();
+            Some((pointer, value))
+        })
+        .collect();
+
+    overrides.sort();
+    overrides
+}
+
+fn apply_overrides(config: &mut Value, overrides: &[(String, String)]) {
+    for &(ref pointer, ref raw) in overrides {
+        let field = pointer[1..].replace('/', ".");
+
+        let value = match config.pointer_mut(pointer) {
+            Some(value) => value,
+            None => {
+                warn!("Ignoring environment override of unknown field {}.", field);
+                continue;
+            }
+        };
+
+        // Strings are taken as they are, everything else is parsed as JSON.
+        *value = match *value {
+            Value::String(_) => Value::String(raw.clone()),
+            _ => serde_json::from_str(raw)
+                .unwrap_or_else(|_| Value::String(raw.clone()))
+        };
+
+        info!("Config field {} is set by the environment.", field);
+    }
+}
+
+// Changes of fields set by the environment, which would silently be lost on
+// the next start.
+fn overridden_changes(
+    old: &Config,
+    new: &Config,
+    overrides: &[(String, String)]
+) -> Vec<FieldError> {
+    let old = serde_json::to_value(old).unwrap();
+    let new = serde_json::to_value(new).unwrap();
+
+    overrides.iter()
+        .filter(|(pointer, _)| old.pointer(pointer) != new.pointer(pointer))
+        .map(|(pointer, _)| {
+            let variable = pointer[1..].replace('/', "__").to_uppercase();
+            FieldError {
+                field: pointer[1..].replace('/', "."),
+                message: format!("is set by the environment variable {}{}",
+                                 ENV_PREFIX, variable),
+            }
+        })
+        .collect()
+}
+
+/// The default config with the fields set by the environment.
+pub fn defaults() -> Config {
+    let mut value = serde_json::to_value(Config::default()).unwrap();
+    apply_overrides(&mut value, &env_overrides());
+    serde_json::from_value(value).unwrap_or_default()
+}
+
+/// Replace the file atomically, so that it is never left empty or half
+/// written by a crash or power loss.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    // The config contains secrets. The mode is only set on creation, so a
+    // file left over by a crash is replaced.
+    fs::remove_file(&tmp_path).ok();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // Persist the rename itself.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// A previous config that can be restored.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ConfigVersion {
+    pub id: String,
+    pub time: DateTime<Utc>,
+}
+
+fn history_dir(path: &Path) -> PathBuf {
+    path.with_extension("history")
+}
+
+// History entries use the same format as the config file.
+fn history_file(path: &Path, id: &str) -> PathBuf {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("json");
+    history_dir(path).join(format!("{}.{}", id, ext))
+}
+
+// Copy the current config file to the history and remove the oldest entries.
+fn record_history(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    add_history(path, &fs::read(path)?)
+}
+
+fn add_history(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = history_dir(path);
+    fs::create_dir_all(&dir)?;
+
+    let id = Utc::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    write_atomic(&history_file(path, &id), data)?;
+
+    let versions = history_in(&dir);
+    for version in versions.iter().skip(HISTORY_SIZE) {
+        fs::remove_file(history_file(path, &version.id))?;
+    }
+
+    Ok(())
+}
+
+// Previous configs, newest first.
+fn history_in(dir: &Path) -> Vec<ConfigVersion> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new()
+    };
+
+    let mut versions: Vec<ConfigVersion> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let id = entry.path().file_stem()?.to_str()?.to_owned();
+            let time = entry.metadata().ok()?.modified().ok()?.into();
+            Some(ConfigVersion { id, time })
+        })
+        .collect();
+
+    // The IDs are timestamps, so they sort chronologically.
+    versions.sort_by(|a, b| b.id.cmp(&a.id));
+    versions
+}
+
+pub fn history() -> Vec<ConfigVersion> {
+    history_in(&history_dir(&path()))
+}
+
+/// Restore a previous config. The current config is added to the history.
+pub fn rollback(id: &str) -> Result<Config, String> {
+    let versions = history();
+    if !versions.iter().any(|version| version.id == id) {
+        return Err(format!("Config version {} not found", id));
+    }
+
+    let file = history_file(&path(), id);
+    let config = Config::read(&file).map_err(|err| err.to_string())?;
+
+    set(&config).map_err(|errors| {
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    })?;
+
+    Ok(config)
+}
+
+/// Fields that differ between two configs, e.g. `audio.level`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigChanges {
+    fields: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn new(old: &Config, new: &Config) -> ConfigChanges {
+        let old = serde_json::to_value(old).unwrap();
+        let new = serde_json::to_value(new).unwrap();
+        let mut changes = ConfigChanges::default();
+        changes.compare("", &old, &new);
+        changes
+    }
+
+    // Objects are compared field by field, everything else as a whole.
+    fn compare(&mut self, path: &str, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+                keys.sort();
+                keys.dedup();
+
+                for key in keys {
+                    let field = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    let null = Value::Null;
+                    self.compare(
+                        &field,
+                        old.get(key).unwrap_or(&null),
+                        new.get(key).unwrap_or(&null)
+                    );
+                }
+            }
+            (old, new) if old != new => self.fields.push(path.to_owned()),
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Whether the field or any field below it changed.
+    pub fn contains(&self, field: &str) -> bool {
+        self.fields.iter().any(|changed| {
+            changed == field ||
+                (changed.starts_with(field) &&
+                 changed[field.len()..].starts_with('.'))
+        })
+    }
+
+    pub fn extend(&mut self, other: ConfigChanges) {
+        for field in other.fields {
+            if !self.fields.contains(&field) {
+                self.fields.push(field);
+            }
+        }
+    }
+}
+
+/// Validation error of a single config field, e.g. `raspager.freq`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    fn check(&mut self, valid: bool, field: &str, message: &str) {
+        if !valid {
+            self.errors.push(FieldError {
+                field: field.to_owned(),
+                message: message.to_owned(),
+            });
+        }
+    }
+
+    fn range<T: PartialOrd + fmt::Display>(&mut self, field: &str, value: T, min: T, max: T) {
+        let message = format!("must be between {} and {}", min, max);
+        self.check(min <= value && value <= max, field, &message);
+    }
+
+    fn serial_port(&mut self, field: &str, port: &str) {
+        self.check(port.starts_with("/dev/"), field,
+                   "must be a device below /dev/");
+    }
+
+    fn baud_rate(&mut self, field: &str, value: usize, allowed: &[usize]) {
+        let names: Vec<String> = allowed.iter().map(usize::to_string).collect();
+        let message = format!("must be one of {}", names.join(", "));
+        self.check(allowed.contains(&value), field, &message);
+    }
+
+    fn address(&mut self, field: &str, address: &str) {
+        self.check(address.parse::<SocketAddr>().is_ok(), field,
+                   "must be an address like 0.0.0.0:8073");
+    }
 }
 
 pub fn get() -> Config {
     CONFIG.read().unwrap().clone()
 }
 
-pub fn set(new_config: &Config) {
+/// Validate and store a new config. Invalid configs and changes of fields set
+/// by the environment are rejected. Secrets that were redacted keep their
+/// stored value. Returns the stored config.
+pub fn set(new_config: &Config) -> Result<Config, Vec<FieldError>> {
+    let mut new_config = new_config.clone();
+    let current = get();
+    new_config.unredact(&current);
+
+    let mut errors = overridden_changes(&current, &new_config, &env_overrides());
+    if let Err(invalid) = new_config.validate() {
+        errors.extend(invalid);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
     let mut config = CONFIG.write().unwrap();
-    *config = new_config.clone();
+    *config = new_config;
+
+    if let Err(err) = record_history(&path()) {
+        error!("Failed to add the config to the history: {}", err);
+    }
     config.save();
+    Ok(config.clone())
+}
+
+/// Use a config that was changed in the file, e.g. on SIGHUP. The previous
+/// config is added to the history, as it is no longer in the file.
+pub fn replace(new_config: Config) -> Result<(), Vec<FieldError>> {
+    new_config.validate()?;
+    let mut config = CONFIG.write().unwrap();
+    let path = path();
+
+    let result = config.file_data(&path)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        .and_then(|data| add_history(&path, data.as_bytes()));
+    if let Err(err) = result {
+        error!("Failed to add the config to the history: {}", err);
+    }
+
+    *config = new_config;
+    Ok(())
 }
 
 impl Config {
-    pub fn load() -> Config {
-        match File::open(CONFIG_FILE) {
-            Ok(mut file) => {
-                let mut data = String::new();
-                file.read_to_string(&mut data).expect(
-                    "Failed to read config file"
-                );
-
-                if let Ok(config) = serde_json::from_str(&data) {
-                    config
-                } else {
-                    error!("Failed to parse config file. Using default.");
-                    Config::default()
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::default();
+
+        self.validate_transmitter(&mut v, "");
+        // GPIO pins and serial ports with the transmitter using them
+        let mut used: Vec<(String, String)> = self.hardware().into_iter()
+            .map(|(_, device)| (device, String::from("the main transmitter")))
+            .collect();
+
+        for (i, other) in self.transmitters.iter().enumerate() {
+            let prefix = format!("transmitters[{}].", i);
+            let field = format!("{}name", prefix);
+
+            // The name identifies the transmitter, e.g. in the queue file.
+            v.check(!other.name.is_empty(), &field, "must not be empty");
+            v.check(other.name.chars().all(|c| {
+                c.is_ascii_alphanumeric() || c == '-' || c == '_'
+            }), &field, "must only contain letters, digits, - and _");
+            v.check(
+                !self.transmitters[..i].iter().any(|t| t.name == other.name),
+                &field, "must be unique"
+            );
+
+            let config = self.with_transmitter(other);
+            config.validate_transmitter(&mut v, &prefix);
+
+            let hardware = config.hardware();
+            for (field, device) in &hardware {
+                let field = format!("{}{}", prefix, field);
+                if v.errors.iter().any(|e| e.field == field) {
+                    continue;
                 }
+                if let Some((_, user)) = used.iter().find(|(d, _)| d == device) {
+                    let message = format!("{} is already used by {}", device, user);
+                    v.check(false, &field, &message);
+                }
+            }
+            let owner = format!("transmitter {}", other.name);
+            used.extend(hardware.into_iter().map(|(_, d)| (d, owner.clone())));
+        }
+
+        if self.gpio.backend == GpioBackend::Cdev {
+            v.check(!self.gpio.chip.is_empty(), "gpio.chip",
+                    "must not be empty");
+        }
+        for (pin, line) in &self.gpio.lines {
+            let field = format!("gpio.lines.{}", pin);
+            v.check(pin.parse::<usize>().is_ok(), &field,
+                    "must be a pin number");
+            v.check(!line.is_empty(), &field, "must not be empty");
+        }
+
+        if !self.master.standalone_mode {
+            v.check(!self.master.server.is_empty(), "master.server",
+                    "must not be empty");
+            v.check(self.master.port != 0, "master.port", "must not be 0");
+        }
+
+        if self.mqtt.enabled {
+            v.check(!self.mqtt.server.is_empty(), "mqtt.server",
+                    "must not be empty");
+            v.check(self.mqtt.port != 0, "mqtt.port", "must not be 0");
+            v.range("mqtt.qos", self.mqtt.qos, 0, 2);
+        }
+
+        if self.tap.enabled {
+            if !self.tap.listen.is_empty() {
+                v.address("tap.listen", &self.tap.listen);
             }
-            Err(_) => {
-                info!("Creating config file from default config.");
-                let config = Config::default();
-                config.save();
-                config
+            if !self.tap.serial_port.is_empty() {
+                v.serial_port("tap.serial_port", &self.tap.serial_port);
+                v.baud_rate("tap.baudrate", self.tap.baudrate, &BAUD_RATES);
             }
+            v.range("tap.priority", self.tap.priority, 1, NUM_PRIORITIES);
+        }
+
+        if self.snpp.enabled {
+            v.address("snpp.listen", &self.snpp.listen);
+            v.range("snpp.priority", self.snpp.priority, 1, NUM_PRIORITIES);
+        }
+
+        for (i, address) in self.frontend.http.iter().enumerate() {
+            v.address(&format!("frontend.http[{}]", i), address);
+        }
+        for (i, address) in self.frontend.websocket.iter().enumerate() {
+            v.address(&format!("frontend.websocket[{}]", i), address);
         }
+        for (i, proxy) in self.frontend.trusted_proxies.iter().enumerate() {
+            v.check(proxy.parse::<IpAddr>().is_ok(),
+                    &format!("frontend.trusted_proxies[{}]", i),
+                    "must be an IP address like 127.0.0.1");
+        }
+
+        for (i, key) in self.api_keys.iter().enumerate() {
+            v.check(key.hash != REDACTED, &format!("api_keys[{}].hash", i),
+                    "unknown API key");
+            for (j, range) in key.rics.iter().enumerate() {
+                v.check(range.from <= range.to,
+                        &format!("api_keys[{}].rics[{}]", i, j),
+                        "from must not be greater than to");
+            }
+        }
+
+        if v.errors.is_empty() { Ok(()) } else { Err(v.errors) }
+    }
+
+    // Check the settings of the selected transmitter type and the routing.
+    // GPIO pins and serial ports used by the transmitter with the field
+    // selecting them.
+    fn hardware(&self) -> Vec<(&'static str, String)> {
+        let gpio = |pin: usize| format!("GPIO pin {}", pin);
+        match self.transmitter {
+            Transmitter::Raspager | Transmitter::Raspager2 => {
+                let pins = match self.transmitter {
+                    Transmitter::Raspager => &RASPAGER1_PINS,
+                    _ => &RASPAGER2_PINS
+                };
+                [pins.le, pins.ce, pins.clk, pins.sdata, pins.muxout,
+                 pins.atclk, pins.atdata, pins.handshake, pins.ptt]
+                    .iter().map(|&pin| ("transmitter", gpio(pin))).collect()
+            }
+            Transmitter::C9000 => {
+                let mut hardware: Vec<_> = [0, 2, 3, 10, 11].iter()
+                    .map(|&pin| ("transmitter", gpio(pin))).collect();
+                hardware.push(("transmitter", Model::get().serial_port().to_owned()));
+                if self.c9000.dummy_enabled {
+                    hardware.push(("c9000.dummy_port", self.c9000.dummy_port.clone()));
+                }
+                hardware
+            }
+            Transmitter::RFM69 => vec![("rfm69.port", self.rfm69.port.clone())],
+            Transmitter::Audio => match self.ptt.method {
+                PttMethod::Gpio => vec![("ptt.gpio_pin", gpio(self.ptt.gpio_pin))],
+                PttMethod::SerialDtr | PttMethod::SerialRts => {
+                    vec![("ptt.serial_port", self.ptt.serial_port.clone())]
+                }
+                #[cfg(hid_ptt)]
+                PttMethod::HidRaw => {
+                    vec![("ptt.hidraw_device", self.ptt.hidraw_device.clone())]
+                }
+            },
+            Transmitter::Dummy => Vec::new()
+        }
+    }
+
+    fn validate_transmitter(&self, v: &mut Validator, prefix: &str) {
+        let f = |field: &str| format!("{}{}", prefix, field);
+
+        match self.transmitter {
+            Transmitter::Raspager | Transmitter::Raspager2 => {
+                let raspager = &self.raspager;
+                v.range(&f("raspager.freq"), raspager.freq,
+                        ADF7012_FREQ_MIN, ADF7012_FREQ_MAX);
+                v.range(&f("raspager.freq_corr"), raspager.freq_corr,
+                        -1024, 1023);
+                v.range(&f("raspager.pa_output_level"),
+                        raspager.pa_output_level, 0, 63);
+                v.range(&f("raspager.mod_deviation"), raspager.mod_deviation,
+                        0, 511);
+            }
+            Transmitter::C9000 => {
+                v.baud_rate(&f("c9000.baudrate"), self.c9000.baudrate as usize,
+                            &BAUD_RATES);
+                if self.c9000.dummy_enabled {
+                    v.serial_port(&f("c9000.dummy_port"),
+                                  &self.c9000.dummy_port);
+                }
+            }
+            Transmitter::RFM69 => {
+                v.serial_port(&f("rfm69.port"), &self.rfm69.port);
+            }
+            Transmitter::Audio => {
+                v.range(&f("audio.level"), self.audio.level, 0, 127);
+                v.baud_rate(&f("audio.baudrate"), self.audio.baudrate,
+                            &AUDIO_BAUD_RATES);
+                v.range(&f("audio.tx_delay"), self.audio.tx_delay, 0, 5000);
+
+                match self.ptt.method {
+                    PttMethod::Gpio => {
+                        // Pins are numbered by the mapping of the model,
+                        // unless the line is selected by name.
+                        let named = self.gpio.lines
+                            .contains_key(&self.ptt.gpio_pin.to_string());
+                        if let (Some(mapping), false) =
+                            (Model::get().pin_mapping(), named)
+                        {
+                            v.range(&f("ptt.gpio_pin"), self.ptt.gpio_pin, 0,
+                                    mapping.len() - 1);
+                        }
+                    }
+                    PttMethod::SerialDtr | PttMethod::SerialRts => {
+                        v.serial_port(&f("ptt.serial_port"),
+                                      &self.ptt.serial_port);
+                    }
+                    #[cfg(hid_ptt)]
+                    PttMethod::HidRaw => {
+                        v.check(self.ptt.hidraw_device.starts_with("/dev/"),
+                                &f("ptt.hidraw_device"),
+                                "must be a device below /dev/");
+                        v.range(&f("ptt.hidraw_gpio_pin"),
+                                self.ptt.hidraw_gpio_pin, 1, 4);
+                    }
+                }
+            }
+            Transmitter::Dummy => {}
+        }
+
+        v.check(self.routing.timeslots.chars().all(|c| c.is_digit(16)),
+                &f("routing.timeslots"), "must only contain hex digits");
+        for (i, range) in self.routing.rics.iter().enumerate() {
+            v.check(range.from <= range.to, &f(&format!("routing.rics[{}]", i)),
+                    "from must not be greater than to");
+        }
+    }
+
+    /// Effective config of the transmitter with the given name from
+    /// `transmitters`, None selects the main transmitter.
+    pub fn for_transmitter(&self, name: Option<&str>) -> Option<Config> {
+        let other = match name {
+            Some(name) => self.transmitters.iter().find(|t| t.name == name)?,
+            None => return Some(self.clone())
+        };
+        Some(self.with_transmitter(other))
+    }
+
+    fn with_transmitter(&self, other: &TransmitterConfig) -> Config {
+        let mut config = self.clone();
+        config.transmitter = other.transmitter;
+        config.ptt = other.ptt.clone();
+        config.raspager = other.raspager.clone();
+        config.c9000 = other.c9000.clone();
+        config.audio = other.audio.clone();
+        config.rfm69 = other.rfm69.clone();
+        config.routing = other.routing.clone();
+        config
+    }
+
+    // The config as stored in the file, without the environment overrides.
+    fn read_stored(path: &Path) -> io::Result<Config> {
+        let mut data = String::new();
+        File::open(path)?.read_to_string(&mut data)?;
+
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        let mut value = Format::from_path(path).parse(&data).map_err(invalid)?;
+        migrate(&mut value);
+        serde_json::from_value(value).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Read and parse the config file without falling back to the default.
+    /// Environment overrides and the auth file are applied.
+    pub fn read(path: &Path) -> io::Result<Config> {
+        Config::read_with(path, &env_overrides())
+    }
+
+    fn read_with(path: &Path, overrides: &[(String, String)]) -> io::Result<Config> {
+        let config = Config::read_stored(path)?;
+
+        let mut value = serde_json::to_value(&config)?;
+        apply_overrides(&mut value, overrides);
+        let mut config: Config = serde_json::from_value(value).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid environment override: {}", err)
+            )
+        })?;
+
+        if !config.master.auth_file.is_empty() {
+            let auth = fs::read_to_string(&config.master.auth_file).map_err(|err| {
+                io::Error::new(err.kind(), format!("{}: {}", config.master.auth_file, err))
+            })?;
+            config.master.auth = auth.trim().to_owned();
+        }
+
+        Ok(config)
+    }
+
+    /// Copy of the config with passwords, keys and hashes replaced.
+    pub fn redacted(&self) -> Config {
+        fn redact(secret: &mut String) {
+            if !secret.is_empty() {
+                *secret = String::from(REDACTED);
+            }
+        }
+
+        let mut config = self.clone();
+        redact(&mut config.master.auth);
+        redact(&mut config.mqtt.password);
+        redact(&mut config.tap.password);
+        for key in config.api_keys.iter_mut() {
+            redact(&mut key.hash);
+        }
+        config
+    }
+
+    /// Restore redacted secrets from the stored config. API keys are matched
+    /// by name.
+    pub fn unredact(&mut self, stored: &Config) {
+        fn restore(secret: &mut String, stored: &str) {
+            if secret == REDACTED {
+                *secret = stored.to_owned();
+            }
+        }
+
+        restore(&mut self.master.auth, &stored.master.auth);
+        restore(&mut self.mqtt.password, &stored.mqtt.password);
+        restore(&mut self.tap.password, &stored.tap.password);
+        for key in self.api_keys.iter_mut() {
+            if let Some(old) = stored.api_keys.iter().find(|old| old.name == key.name) {
+                restore(&mut key.hash, &old.hash);
+            }
+        }
+    }
+
+    /// Read the config file, which is created from the default config if it
+    /// does not exist. A file that can not be read is never replaced by the
+    /// default config, the error has to be fixed first.
+    pub fn load() -> io::Result<Config> {
+        Config::load_from(&path(), &env_overrides())
+    }
+
+    fn load_from(path: &Path, overrides: &[(String, String)]) -> io::Result<Config> {
+        if !path.exists() {
+            info!("Creating config file from default config.");
+            Config::default().save_to(path);
+        }
+
+        let config = Config::read_with(path, overrides)?;
+
+        // A config that ran on a previous version may be rejected by a
+        // stricter validation. It is still used, as the defaults would
+        // silently replace the transmitter.
+        if let Err(errors) = config.validate() {
+            for error in errors {
+                error!("Invalid config: {}", error);
+            }
+            warn!("Using the invalid config file anyway, fix it and check \
+                   it with --check-config.");
+        }
+
+        Ok(config)
     }
 
     pub fn save(&self) {
-        let data = serde_json::to_vec_pretty(self).unwrap();
+        self.save_to(&path());
+    }
+
+    fn save_to(&self, path: &Path) {
+        let data = match self.file_data(path) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to serialize config: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = write_atomic(path, data.as_bytes()) {
+            error!("Failed to write config file: {}", err);
+        }
+    }
+
+    // Contents of the config file, without the auth key if it is read from
+    // a file and with the stored values of fields set by the environment.
+    fn file_data(&self, path: &Path) -> Result<String, String> {
+        let mut config = self.clone();
+        config.version = CONFIG_VERSION;
+
+        if !config.master.auth_file.is_empty() {
+            config.master.auth.clear();
+        }
 
-        let mut new_file = File::create(CONFIG_FILE).expect(
-            "Couldn't create config file"
-        );
+        let overrides = env_overrides();
+        let mut value = serde_json::to_value(&config).unwrap();
+        if !overrides.is_empty() {
+            let stored = Config::read_stored(path).unwrap_or_default();
+            let stored = serde_json::to_value(&stored).unwrap();
 
-        new_file.write_all(data.as_slice()).expect(
-            "Couldn't write to config file"
-        );
+            for (pointer, _) in &overrides {
+                if let (Some(value), Some(stored)) =
+                    (value.pointer_mut(pointer), stored.pointer(pointer))
+                {
+                    *value = stored.clone();
+                }
+            }
+        }
+
+        Format::from_path(path).serialize(&value).map_err(|err| err.to_string())
     }
 }
+
+#[test]
+pub fn test_config_validation() {
+    assert_eq!(Config::default().validate(), Ok(()));
+
+    let mut config = Config::default();
+    config.transmitter = Transmitter::Raspager;
+    config.raspager.freq = 2_400_000_000;
+    config.raspager.pa_output_level = 64;
+    config.frontend.http = vec![String::from("localhost")];
+    config.frontend.trusted_proxies = vec![String::from("proxy")];
+    config.tap.enabled = true;
+    config.tap.serial_port = String::from("ttyS0");
+    config.tap.baudrate = 1000;
+    config.gpio.lines.insert(String::from("ptt"), String::from("GPIO17"));
+
+    let errors = config.validate().unwrap_err();
+    let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, vec![
+        "raspager.freq",
+        "raspager.pa_output_level",
+        "gpio.lines.ptt",
+        "tap.serial_port",
+        "tap.baudrate",
+        "frontend.http[0]",
+        "frontend.trusted_proxies[0]"
+    ]);
+    assert_eq!(errors[0].message, "must be between 75000000 and 1000000000");
+}
+
+#[test]
+pub fn test_config_migration() {
+    let mut value = json!({
+        "version": 1,
+        "raspager": { "freq": 439987500 },
+        "transmitters": [
+            { "name": "Audio TX" }, { "name": "Audio_TX" }, { "name": "" }
+        ]
+    });
+    migrate(&mut value);
+
+    let config: Config = serde_json::from_value(value).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.raspager.freq, 439987500);
+    let names: Vec<&str> =
+        config.transmitters.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["Audio_TX", "Audio_TX2", "3"]);
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+pub fn test_config_history() {
+    let dir = std::env::temp_dir().join("unipager-test-history");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.json");
+
+    for i in 0..HISTORY_SIZE + 2 {
+        write_atomic(&path, format!("{{\"version\": {}}}", i).as_bytes()).unwrap();
+        record_history(&path).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+
+    let versions = history_in(&history_dir(&path));
+    assert_eq!(versions.len(), HISTORY_SIZE);
+
+    // The newest entry is a copy of the current file.
+    let newest = history_dir(&path).join(format!("{}.json", versions[0].id));
+    assert_eq!(fs::read(newest).unwrap(), fs::read(&path).unwrap());
+    assert!(!dir.join("config.json.tmp").exists());
+}
+
+#[test]
+pub fn test_config_changes() {
+    let old = Config::default();
+    let mut new = old.clone();
+    assert!(ConfigChanges::new(&old, &new).is_empty());
+
+    new.audio.level = 100;
+    new.master.auth = String::from("secret");
+    let changes = ConfigChanges::new(&old, &new);
+
+    assert!(changes.contains("audio"));
+    assert!(changes.contains("audio.level"));
+    assert!(!changes.contains("audio.device"));
+    assert!(changes.contains("master"));
+    assert!(!changes.contains("mas"));
+    assert!(!changes.contains("raspager"));
+}
+
+#[test]
+pub fn test_config_overrides() {
+    let data = Format::Toml.serialize(&Config::default()).unwrap();
+    let mut value = Format::Toml.parse(&data).unwrap();
+
+    apply_overrides(&mut value, &[
+        (String::from("/master/port"), String::from("43434")),
+        (String::from("/master/call"), String::from("123")),
+        (String::from("/frontend/http"), String::from("[\"[::1]:8073\"]")),
+        (String::from("/unknown"), String::from("1"))
+    ]);
+
+    let config: Config = serde_json::from_value(value).unwrap();
+    assert_eq!(config.master.port, 43434);
+    assert_eq!(config.master.call, "123");
+    assert_eq!(config.frontend.http, vec![String::from("[::1]:8073")]);
+    assert_eq!(config.master.fallback, Config::default().master.fallback);
+
+    let mut config = Config::default();
+    config.master.auth = String::from("secret");
+    assert_eq!(config.redacted().master.auth, REDACTED);
+    assert_eq!(config.redacted().mqtt.password, "");
+
+    let overrides = [(String::from("/master/port"), String::from("43434"))];
+    let mut changed = config.clone();
+    changed.master.call = String::from("changed");
+    assert!(overridden_changes(&config, &changed, &overrides).is_empty());
+    changed.master.port = 1234;
+    let errors = overridden_changes(&config, &changed, &overrides);
+    assert_eq!(errors[0].field, "master.port");
+    assert_eq!(errors[0].message,
+               "is set by the environment variable UNIPAGER_MASTER__PORT");
+
+    let mut redacted = config.redacted();
+    redacted.mqtt.password = String::from("new");
+    redacted.unredact(&config);
+    assert_eq!(redacted.master.auth, "secret");
+    assert_eq!(redacted.mqtt.password, "new");
+}
+
+#[test]
+pub fn test_config_transmitters() {
+    let mut config = Config::default();
+    config.transmitters.push(TransmitterConfig {
+        name: String::from("second"),
+        transmitter: Transmitter::Raspager,
+        routing: RoutingConfig {
+            rics: vec![RicRange { from: 1000, to: 1999 }],
+            timeslots: String::from("02x"),
+        },
+        ..TransmitterConfig::default()
+    });
+    config.transmitters[0].raspager.pa_output_level = 64;
+
+    let second = config.for_transmitter(Some("second")).unwrap();
+    assert_eq!(second.transmitter.to_string(), "Raspager1");
+    assert!(second.routing.accepts(1500));
+    assert!(!second.routing.accepts(2000));
+    assert!(config.routing.accepts(2000));
+    assert!(config.for_transmitter(Some("third")).is_none());
+
+    let errors = config.validate().unwrap_err();
+    let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, vec![
+        "transmitters[0].raspager.pa_output_level",
+        "transmitters[0].routing.timeslots"
+    ]);
+
+    config.transmitters[0].raspager.pa_output_level = 63;
+    config.transmitters[0].routing.timeslots = String::from("02");
+    config.transmitters.push(config.transmitters[0].clone());
+    let errors = config.validate().unwrap_err();
+    assert_eq!(errors[0].field, "transmitters[1].name");
+    assert_eq!(errors[0].message, "must be unique");
+
+    // The PTT of the audio transmitter is the PTT pin of the RasPager.
+    config.transmitters[1].name = String::from("third");
+    config.transmitters[1].transmitter = Transmitter::Audio;
+    config.transmitters[1].ptt.gpio_pin = 4;
+    let errors = config.validate().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field, "transmitters[1].ptt.gpio_pin");
+    assert_eq!(errors[0].message, "GPIO pin 4 is already used by transmitter second");
+}
+
+#[test]
+pub fn test_config_load_errors() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join("unipager-test-load");
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.json");
+
+    let mut config = Config::default();
+    config.master.auth_file = dir.join("auth").to_string_lossy().into_owned();
+    let data = Format::Json.serialize(&config).unwrap();
+    fs::write(&path, &data).unwrap();
+
+    // Neither a missing auth file nor an invalid override falls back to the
+    // default config.
+    let err = Config::load_from(&path, &[]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    fs::write(dir.join("auth"), "secret\n").unwrap();
+    assert_eq!(Config::load_from(&path, &[]).unwrap().master.auth, "secret");
+
+    let overrides = [(String::from("/master/port"), String::from("none"))];
+    let err = Config::load_from(&path, &overrides).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), data);
+
+    // Written files are only readable by the owner.
+    write_atomic(&path, data.as_bytes()).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
@@ -0,0 +1,280 @@
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{future::FutureExt, stream::StreamExt, select, pin_mut};
+use futures_timer::Delay;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+use crate::config::{Config, ConfigChanges};
+use crate::event::{Event, EventHandler, EventReceiver, Topic};
+use crate::message::{Message, ProtocolMessage};
+use crate::metrics;
+use crate::pocsag::{self, MessageType};
+use crate::telemetry;
+use crate::timeslots::TimeSlots;
+
+// The legacy protocol has no priorities, all calls use the same one.
+const PRIORITY: usize = 3;
+
+static MESSAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Parse a call in the format `#XX T:S:RIC:F:TEXT` where XX is the hex
+// sequence number, T the message type, S the speed, RIC the hex address
+// and F the function bits. The sequence number is only used for the ack,
+// as it repeats after 256 calls.
+fn parse_message(line: &str) -> Option<(u8, Message)> {
+    let id = u8::from_str_radix(line.get(1..3)?, 16).ok()?;
+    let mut parts = line.get(4..)?.splitn(5, ':');
+
+    let mtype = match parts.next()? {
+        "5" => MessageType::Numeric,
+        "6" => MessageType::AlphaNum,
+        _ => return None,
+    };
+
+    let _speed = parts.next()?;
+    let ric = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let func = parts.next()?.parse().ok()?;
+    let data = parts.next().unwrap_or("").to_owned();
+
+    let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let message = Message {
+        id: format!("fmx-{}-{}", ::chrono::Utc::now().timestamp(), counter),
+        priority: PRIORITY,
+        origin: "funkrufmaster".to_owned(),
+        expires_on: None,
+        message: ProtocolMessage::Pocsag(pocsag::Message {
+            mtype,
+            ric,
+            func,
+            data,
+            ..pocsag::Message::default()
+        })
+    };
+
+    Some((id, message))
+}
+
+struct MasterConnection {
+    config: Config,
+    event_handler: EventHandler,
+    event_receiver: EventReceiver,
+    restart: bool
+}
+
+impl MasterConnection {
+    pub fn new(config: Config, event_handler: EventHandler) -> MasterConnection {
+        let rx = event_handler.subscribe(&[
+            Topic::Config, Topic::Telemetry, Topic::MessageStatus, Topic::Control
+        ]);
+
+        MasterConnection {
+            config,
+            event_handler,
+            event_receiver: rx,
+            restart: true
+        }
+    }
+
+    pub async fn start(&mut self) {
+        loop {
+            if let Err(err) = self.run().await {
+                error!("Master connection failed: {}", err);
+            }
+
+            if !self.restart {
+                return;
+            }
+
+            warn!(
+                "Connection to master lost. Reconnecting in {} Seconds...",
+                self.config.master.reconnect_timeout
+            );
+            Delay::new(Duration::from_secs(self.config.master.reconnect_timeout)).await;
+            metrics::reconnect();
+
+            while let Ok(Some(event)) = self.event_receiver.try_next() {
+                self.handle_event(event);
+            }
+
+            if !self.restart {
+                return;
+            }
+        }
+    }
+
+    async fn run(&mut self) -> io::Result<()> {
+        let host = self.config.master.server.to_owned();
+        let port = self.config.master.port;
+
+        telemetry_update!(node: &|node: &mut telemetry::Node| {
+            *node = telemetry::Node {
+                name: host.to_owned(),
+                port: port,
+                connected: false,
+                connected_since: None
+            };
+        });
+
+        info!("Connecting to {}:{}...", host, port);
+
+        let stream = TcpStream::connect((&*host, port)).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let login = format!(
+            "[UniPager v{} {} {}]\r\n",
+            env!("CARGO_PKG_VERSION"),
+            self.config.master.call,
+            self.config.master.auth
+        );
+        writer.write_all(login.as_bytes()).await?;
+
+        info!("Connected to master. Listening for incoming calls.");
+
+        telemetry_update!(node: &|node: &mut telemetry::Node| {
+            node.connected = true;
+            node.connected_since = Some(::chrono::Utc::now());
+        });
+
+        let result = loop {
+            let next_line = lines.next_line().fuse();
+            let next_event = self.event_receiver.next().fuse();
+
+            pin_mut!(next_line, next_event);
+
+            select! {
+                line = next_line => {
+                    match line {
+                        Ok(Some(line)) => {
+                            let response = self.handle_line(line.trim()).await;
+                            if let Err(err) = writer.write_all(response.as_bytes()).await {
+                                break Err(err);
+                            }
+                        }
+                        Ok(None) => break Ok(()),
+                        Err(err) => break Err(err)
+                    }
+                },
+                event = next_event => {
+                    match event {
+                        Some(event) => {
+                            if self.handle_event(event) {
+                                break Ok(());
+                            }
+                        }
+                        None => break Ok(())
+                    }
+                }
+            }
+        };
+
+        telemetry_update!(node: &|node: &mut telemetry::Node| {
+            node.connected = false;
+            node.connected_since = None;
+        });
+
+        result
+    }
+
+    // Handle a line from the master and return the response.
+    async fn handle_line(&mut self, line: &str) -> String {
+        match line.chars().next() {
+            Some('#') => {
+                match parse_message(line) {
+                    Some((id, msg)) => {
+                        info!("Message received: {:?}", msg);
+                        let event = Event::MessageReceived(msg);
+                        self.event_handler.publish_async(event).await;
+                        format!("#{:02x} +\r\n", id.wrapping_add(1))
+                    }
+                    None => {
+                        warn!("Could not decode incoming message: {}", line);
+                        "-\r\n".to_owned()
+                    }
+                }
+            }
+            Some('2') => {
+                // Time synchronisation request, answered with our own time.
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let ticks = (now.as_millis() / 100) as u64 & 0xffff;
+                format!("{}:{:04x}\r\n+\r\n", line, ticks)
+            }
+            Some('3') => "+\r\n".to_owned(),
+            Some('4') => {
+                let slots = line.get(2..).unwrap_or("");
+                match TimeSlots::from_str(slots) {
+                    Ok(timeslots) => {
+                        info!("Timeslots updated: {:?}", timeslots);
+                        let event = Event::TimeslotsUpdate(timeslots);
+                        self.event_handler.publish_async(event).await;
+                        "+\r\n".to_owned()
+                    }
+                    Err(_) => "-\r\n".to_owned()
+                }
+            }
+            _ => {
+                warn!("Unknown request from master: {}", line);
+                "-\r\n".to_owned()
+            }
+        }
+    }
+
+    // Handle an event and return whether the connection should be closed.
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::ConfigUpdate(new_config) => {
+                let changes = ConfigChanges::new(&self.config, &new_config);
+                self.config = new_config;
+                if changes.contains("master") {
+                    self.restart = true;
+                    true
+                }
+                else {
+                    false
+                }
+            }
+            Event::Restart => {
+                self.restart = true;
+                true
+            }
+            Event::Shutdown => {
+                self.restart = false;
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+pub fn start(runtime: &Runtime, config: &Config, event_handler: EventHandler) {
+    let mut conn = MasterConnection::new(config.clone(), event_handler.clone());
+    runtime.spawn(async move { conn.start().await; });
+}
+
+#[test]
+pub fn test_parse_message() {
+    let (id, msg) = parse_message("#1F 6:1:9C8:3:Hello: World").unwrap();
+    assert_eq!(id, 0x1F);
+
+    // Calls with the same sequence number are still distinct messages.
+    let (_, other) = parse_message("#1F 6:1:9C8:3:Hello: World").unwrap();
+    assert_ne!(msg.id, other.id);
+
+    match msg.message {
+        ProtocolMessage::Pocsag(msg) => {
+            assert_eq!(msg.ric, 0x9C8);
+            assert_eq!(msg.func, 3);
+            assert_eq!(msg.data, "Hello: World");
+        }
+    }
+
+    assert!(parse_message("#1F 9:1:9C8:3:Hello").is_none());
+}
@@ -0,0 +1,18 @@
+mod funkrufmaster;
+mod rabbitmq;
+
+use tokio::runtime::Runtime;
+
+use crate::config::{Config, MasterProtocol};
+use crate::event::EventHandler;
+
+pub fn start(runtime: &Runtime, config: &Config, event_handler: EventHandler) {
+    match config.master.protocol {
+        MasterProtocol::Core => {
+            rabbitmq::start(runtime, config, event_handler)
+        }
+        MasterProtocol::FunkrufMaster => {
+            funkrufmaster::start(runtime, config, event_handler)
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use async_std::stream::interval;
 use lapin::{self, message::Delivery, options::*, types::FieldTable,
             BasicProperties, Connection, ConnectionProperties, Channel};
 use futures::{future::FutureExt, stream::StreamExt, select, pin_mut};
@@ -8,26 +9,37 @@ use tokio::{self, runtime::Runtime};
 use tokio_amqp::*;
 use serde_json;
 
-use crate::config::Config;
+use crate::config::{Config, ConfigChanges};
 use crate::core;
-use crate::event::{self, Event, EventHandler, EventReceiver};
-use crate::message::Message;
+use crate::event::{Event, EventHandler, EventReceiver, Topic};
+use crate::message::{Message, StatusReport};
+use crate::metrics;
 use crate::telemetry;
 use crate::timeslots::TimeSlots;
 
+// Status reports are sent in batches of at most this many reports.
+const STATUS_BATCH_SIZE: usize = 50;
+// Pending status reports are flushed at least this often.
+const STATUS_FLUSH_INTERVAL: u64 = 5;
+// Maximum number of reports kept while the connection is down.
+const STATUS_BUFFER_LIMIT: usize = 1000;
+
 struct CoreConnection {
     config: Config,
     event_handler: EventHandler,
     event_receiver: EventReceiver,
     routing_key: String,
     telemetry_routing_key: String,
+    status_routing_key: String,
+    status_reports: Vec<StatusReport>,
     restart: bool
 }
 
 impl CoreConnection {
     pub fn new(config: Config, event_handler: EventHandler) -> CoreConnection {
-        let (tx, rx) = event::channel();
-        event_handler.publish(Event::RegisterConnection(tx));
+        let rx = event_handler.subscribe(&[
+            Topic::Config, Topic::Telemetry, Topic::MessageStatus, Topic::Control
+        ]);
 
         CoreConnection {
             config,
@@ -35,6 +47,8 @@ impl CoreConnection {
             event_receiver: rx,
             routing_key: "".to_owned(),
             telemetry_routing_key: "".to_owned(),
+            status_routing_key: "".to_owned(),
+            status_reports: Vec::new(),
             restart: true
         }
     }
@@ -47,12 +61,14 @@ impl CoreConnection {
                 info!("Bootstrap successful. Found {} nodes.", response.nodes.len());
 
                 let timeslots = TimeSlots::from_vec(response.timeslots);
-                self.event_handler.publish(Event::TimeslotsUpdate(timeslots));
+                let event = Event::TimeslotsUpdate(timeslots);
+                self.event_handler.publish_async(event).await;
 
                 info!("Timeslots updated: {:?}", timeslots);
 
                 self.run().await;
                 warn!("RabbitMQ Connection lost.");
+                metrics::reconnect();
             }
             else {
                 error!(
@@ -60,6 +76,7 @@ impl CoreConnection {
                     self.config.master.reconnect_timeout
                 );
                 Delay::new(Duration::from_secs(self.config.master.reconnect_timeout)).await;
+                metrics::reconnect();
             }
         }
     }
@@ -71,6 +88,7 @@ impl CoreConnection {
 
         self.routing_key = call.to_owned();
         self.telemetry_routing_key = format!("transmitter.{}", call).to_owned();
+        self.status_routing_key = format!("transmitter.{}.status", call).to_owned();
         let auth_key = self.config.master.auth.to_owned();
 
         let host = &self.config.master.server;
@@ -130,11 +148,14 @@ impl CoreConnection {
             node.connected_since = Some(::chrono::Utc::now());
         });
 
+        let mut status_flush = interval(Duration::from_secs(STATUS_FLUSH_INTERVAL));
+
         loop {
             let next_delivery = consumer.next().fuse();
             let next_event = self.event_receiver.next().fuse();
+            let next_flush = status_flush.next().fuse();
 
-            pin_mut!(next_delivery, next_event);
+            pin_mut!(next_delivery, next_event, next_flush);
 
             select! {
                 delivery = next_delivery => {
@@ -153,6 +174,9 @@ impl CoreConnection {
                         break;
                     }
                 },
+                _ = next_flush => {
+                    self.send_status_reports(&channel).await?;
+                },
                 complete => {
                     break;
                 }
@@ -174,7 +198,7 @@ impl CoreConnection {
 
         if let Some(msg) = msg {
             info!("Message received: {:?}", msg);
-            self.event_handler.publish(Event::MessageReceived(msg));
+            self.event_handler.publish_async(Event::MessageReceived(msg)).await;
         }
         else {
             warn!("Could not decode incoming message");
@@ -210,11 +234,27 @@ impl CoreConnection {
                     self.send_telemetry(channel, serde_json::to_vec(&telemetry).unwrap()).await?;
                 }
             }
+            Event::MessageStatus(report) => {
+                self.status_reports.push(report);
+
+                if let Some(channel) = channel {
+                    if self.status_reports.len() >= STATUS_BATCH_SIZE {
+                        self.send_status_reports(channel).await?;
+                    }
+                }
+                else if self.status_reports.len() > STATUS_BUFFER_LIMIT {
+                    let excess = self.status_reports.len() - STATUS_BUFFER_LIMIT;
+                    self.status_reports.drain(..excess);
+                }
+            }
             Event::ConfigUpdate(new_config) => {
-                self.restart = true;
+                let changes = ConfigChanges::new(&self.config, &new_config);
                 self.config = new_config;
-                if let Some(conn) = conn {
-                    conn.close(0, "reconfig").await?;
+                if changes.contains("master") {
+                    self.restart = true;
+                    if let Some(conn) = conn {
+                        conn.close(0, "reconfig").await?;
+                    }
                 }
             }
             Event::Restart => {
@@ -234,6 +274,25 @@ impl CoreConnection {
         Ok(())
     }
 
+    async fn send_status_reports(&mut self, channel: &Channel) -> Result<(), lapin::Error> {
+        while !self.status_reports.is_empty() {
+            let count = self.status_reports.len().min(STATUS_BATCH_SIZE);
+            let data = serde_json::to_vec(&self.status_reports[..count]).unwrap();
+
+            channel
+                .basic_publish(
+                    "dapnet.telemetry",
+                    &*self.status_routing_key,
+                    BasicPublishOptions::default(),
+                    data,
+                    BasicProperties::default()
+                ).await?;
+
+            self.status_reports.drain(..count);
+        }
+        Ok(())
+    }
+
     async fn send_telemetry(&self, channel: &Channel, data: Vec<u8>) -> Result<(), lapin::Error> {
         channel
             .basic_publish(
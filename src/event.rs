@@ -1,16 +1,34 @@
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use futures;
-use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
-use futures_util::StreamExt;
-use tokio::runtime::Runtime;
+use futures::channel::mpsc::{Receiver, Sender};
+use futures_timer::Delay;
 use serde_json;
 
 use crate::telemetry::Telemetry;
 use crate::config::Config;
-use crate::message::Message;
+use crate::message::{Message, StatusReport};
+use crate::metrics;
 use crate::timeslots::{TimeSlot, TimeSlots};
 
+// Number of events a subscriber can lag behind before publishing waits, lossy
+// events are dropped instead.
+const CHANNEL_CAPACITY: usize = 1024;
+// A subscriber that does not take an event within this time is considered
+// stuck, the event is dropped.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // Lossy events dropped since the last report
+    static ref DROPPED: AtomicUsize = AtomicUsize::new(0);
+    static ref LAST_DROP_REPORT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
 #[derive(Clone, Debug)]
 pub enum Event {
     TelemetryUpdate(Telemetry),
@@ -19,114 +37,322 @@ pub enum Event {
     TimeslotsUpdate(TimeSlots),
     ConfigUpdate(Config),
     MessageReceived(Message),
-    RegisterConnection(EventSender),
-    RegisterWebsocket(EventSender),
-    RegisterScheduler(mpsc::Sender<Event>),
-    RegisterMain(EventSender),
+    MessageStatus(StatusReport),
     Log(u8, String),
     Test,
     Shutdown,
     Restart
 }
 
-pub type EventReceiver = UnboundedReceiver<Event>;
-pub type EventSender = UnboundedSender<Event>;
-
-#[derive(Clone)]
-pub struct EventHandler(EventSender);
+/// Category of events a component can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topic {
+    Telemetry,
+    Timeslot,
+    Timeslots,
+    Config,
+    Message,
+    MessageStatus,
+    Log,
+    // Test, restart and shutdown requests
+    Control
+}
 
-impl EventHandler {
-    pub fn publish(&self, event: Event) {
-        self.0.unbounded_send(event).unwrap();
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::TelemetryUpdate(_) |
+            Event::TelemetryPartialUpdate(_) => Topic::Telemetry,
+            Event::Timeslot(_) => Topic::Timeslot,
+            Event::TimeslotsUpdate(_) => Topic::Timeslots,
+            Event::ConfigUpdate(_) => Topic::Config,
+            Event::MessageReceived(_) => Topic::Message,
+            Event::MessageStatus(_) => Topic::MessageStatus,
+            Event::Log(_, _) => Topic::Log,
+            Event::Test | Event::Shutdown | Event::Restart => Topic::Control
+        }
     }
 }
 
-#[derive(Default)]
-pub struct EventDispatcher {
-    pub connection: Option<EventSender>,
-    pub scheduler: Option<mpsc::Sender<Event>>,
-    pub websocket: Option<EventSender>,
-    pub main: Option<EventSender>
+impl Topic {
+    // Periodic updates that are superseded by the next one anyway
+    fn is_lossy(&self) -> bool {
+        matches!(self, Topic::Telemetry | Topic::Timeslot | Topic::Log)
+    }
 }
 
-impl EventDispatcher {
+pub type EventReceiver = Receiver<Event>;
 
+#[derive(Clone)]
+enum EventSender {
+    Async(Sender<Event>),
+    Sync(mpsc::SyncSender<Event>)
 }
 
-pub fn channel() -> (EventSender, EventReceiver) {
-    futures::channel::mpsc::unbounded()
+enum Delivery {
+    Sent,
+    Full(Event),
+    Closed
 }
 
-pub fn start(runtime: &Runtime) -> EventHandler {
-    let (event_tx, event_rx) = channel();
+#[derive(Clone)]
+struct Subscriber {
+    id: usize,
+    topics: Vec<Topic>,
+    sender: EventSender
+}
 
-    runtime.spawn(async move {
-        let mut dispatcher = EventDispatcher::default();
-        let mut event_rx = event_rx;
+impl Subscriber {
+    fn try_send(&mut self, event: Event) -> Delivery {
+        match self.sender {
+            EventSender::Async(ref mut tx) => match tx.try_send(event) {
+                Ok(()) => Delivery::Sent,
+                Err(err) if err.is_full() => Delivery::Full(err.into_inner()),
+                Err(_) => Delivery::Closed
+            },
+            EventSender::Sync(ref tx) => match tx.try_send(event) {
+                Ok(()) => Delivery::Sent,
+                Err(mpsc::TrySendError::Full(event)) => Delivery::Full(event),
+                Err(mpsc::TrySendError::Disconnected(_)) => Delivery::Closed
+            }
+        }
+    }
 
-        while let Some(event) = event_rx.next().await {
-            match event {
-                Event::RegisterConnection(tx) => {
-                    dispatcher.connection = Some(tx);
-                }
-                Event::RegisterWebsocket(tx) => {
-                    dispatcher.websocket = Some(tx);
-                }
-                Event::RegisterScheduler(tx) => {
-                    dispatcher.scheduler = Some(tx);
-                }
-                Event::RegisterMain(tx) => {
-                    dispatcher.main = Some(tx);
-                }
-                Event::ConfigUpdate(_) => {
-                    dispatcher.connection.as_ref().map(|tx| {
-                        tx.unbounded_send(event.clone()).ok();
-                    });
-                    dispatcher.scheduler.as_ref().map(|tx| {
-                        tx.send(event).ok();
-                    });
-                }
-                Event::TelemetryUpdate(_) |
-                Event::TelemetryPartialUpdate(_) => {
-                    dispatcher.websocket.as_ref().map(|tx| {
-                        tx.unbounded_send(event.clone()).ok();
-                    });
-                    dispatcher.connection.as_ref().map(|tx| {
-                        tx.unbounded_send(event).ok();
-                    });
-                }
-                Event::Log(_, _) | Event::Timeslot(_) => {
-                    dispatcher.websocket.as_ref().map(|tx| {
-                        tx.unbounded_send(event).ok();
-                    });
-                }
-                Event::MessageReceived(_) => {
-                    dispatcher.scheduler.as_ref().map(|tx| {
-                        tx.send(event.clone()).ok();
-                    });
-                    dispatcher.websocket.as_ref().map(|tx| {
-                        tx.unbounded_send(event).ok();
-                    });
-                }
-                Event::TimeslotsUpdate(_) | Event::Test => {
-                    dispatcher.scheduler.as_ref().map(|tx| {
-                        tx.send(event.clone()).ok();
-                    });
+    // Deliver the event, blocking the thread until the subscriber has room
+    // for it. Returns whether the subscriber is still alive.
+    fn send_blocking(&mut self, event: Event) -> bool {
+        let topic = event.topic();
+        let deadline = Instant::now() + PUBLISH_TIMEOUT;
+        let mut event = event;
+
+        loop {
+            match self.try_send(event) {
+                Delivery::Sent => return true,
+                Delivery::Closed => return false,
+                Delivery::Full(full) => {
+                    if !wait_for_room(topic, deadline) {
+                        return true;
+                    }
+                    event = full;
+                    thread::sleep(RETRY_INTERVAL);
                 }
-                Event::Shutdown | Event::Restart => {
-                    dispatcher.connection.as_ref().map(|tx| {
-                        tx.unbounded_send(event.clone()).ok();
-                    });
-                    dispatcher.scheduler.as_ref().map(|tx| {
-                        tx.send(event.clone()).ok();
-                    });
-                    dispatcher.main.as_ref().map(|tx| {
-                        tx.unbounded_send(event).ok();
-                    });
+            }
+        }
+    }
+
+    // Deliver the event, waiting until the subscriber has room for it.
+    async fn send(&mut self, event: Event) -> bool {
+        let topic = event.topic();
+        let deadline = Instant::now() + PUBLISH_TIMEOUT;
+        let mut event = event;
+
+        loop {
+            match self.try_send(event) {
+                Delivery::Sent => return true,
+                Delivery::Closed => return false,
+                Delivery::Full(full) => {
+                    if !wait_for_room(topic, deadline) {
+                        return true;
+                    }
+                    event = full;
+                    Delay::new(RETRY_INTERVAL).await;
                 }
-            };
+            }
+        }
+    }
+}
+
+// Whether to wait for a full subscriber. Lossy events are dropped right away,
+// all others after the publish timeout.
+fn wait_for_room(topic: Topic, deadline: Instant) -> bool {
+    if topic.is_lossy() {
+        dropped(topic);
+        false
+    } else if Instant::now() >= deadline {
+        error!("Subscriber did not accept the {:?} event within {} seconds, \
+                dropping it.", topic, PUBLISH_TIMEOUT.as_secs());
+        metrics::event_dropped();
+        false
+    } else {
+        true
+    }
+}
+
+// Count a dropped lossy event. The drops are reported at most once per
+// interval, as log records are lossy events themselves.
+fn dropped(topic: Topic) {
+    metrics::event_dropped();
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+
+    {
+        let mut last_report = LAST_DROP_REPORT.lock().unwrap();
+        match *last_report {
+            Some(time) if time.elapsed() < DROP_REPORT_INTERVAL => return,
+            _ => *last_report = Some(Instant::now())
+        }
+    }
+
+    warn!(
+        "Dropped {} events, the last one of topic {:?}, as a subscriber \
+         did not keep up.",
+        DROPPED.swap(0, Ordering::Relaxed), topic
+    );
+}
+
+/// Publish/subscribe event bus. Every event is delivered to all subscribers
+/// of its topic.
+#[derive(Clone)]
+pub struct EventHandler {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    next_id: Arc<AtomicUsize>
+}
+
+impl EventHandler {
+    pub fn new() -> EventHandler {
+        EventHandler {
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    fn add(&self, topics: &[Topic], sender: EventSender) {
+        self.subscribers.lock().unwrap().push(Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            topics: topics.to_vec(),
+            sender
+        });
+    }
+
+    fn remove(&self, ids: &[usize]) {
+        if !ids.is_empty() {
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|subscriber| !ids.contains(&subscriber.id));
+        }
+    }
+
+    fn subscribers(&self, topic: Topic) -> Vec<Subscriber> {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|subscriber| subscriber.topics.contains(&topic))
+            .cloned()
+            .collect()
+    }
+
+    /// Subscribe to the given topics from async code.
+    pub fn subscribe(&self, topics: &[Topic]) -> EventReceiver {
+        let (tx, rx) = futures::channel::mpsc::channel(CHANNEL_CAPACITY);
+        self.add(topics, EventSender::Async(tx));
+        rx
+    }
+
+    /// Subscribe to the given topics from a thread.
+    pub fn subscribe_sync(&self, topics: &[Topic]) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        self.add(topics, EventSender::Sync(tx));
+        rx
+    }
+
+    /// Publish an event from a thread. If a subscriber can not keep up, lossy
+    /// events like telemetry are dropped and publishing all others blocks
+    /// until it has room again. Use `publish_async` in async code.
+    pub fn publish(&self, event: Event) {
+        let mut closed = Vec::new();
+
+        for mut subscriber in self.subscribers(event.topic()) {
+            if !subscriber.send_blocking(event.clone()) {
+                closed.push(subscriber.id);
+            }
+        }
+
+        self.remove(&closed);
+    }
+
+    /// Publish an event from async code, see `publish`.
+    pub async fn publish_async(&self, event: Event) {
+        let mut closed = Vec::new();
+
+        for mut subscriber in self.subscribers(event.topic()) {
+            if !subscriber.send(event.clone()).await {
+                closed.push(subscriber.id);
+            }
+        }
+
+        self.remove(&closed);
+    }
+}
+
+#[test]
+pub fn test_event_routing() {
+    use futures::executor::block_on;
+    use futures_util::StreamExt;
+
+    let event_handler = EventHandler::new();
+    let mut control = event_handler.subscribe(&[Topic::Control]);
+    let mut logs = event_handler.subscribe(&[Topic::Log, Topic::Timeslot]);
+    let config = event_handler.subscribe_sync(&[Topic::Config, Topic::Control]);
+
+    event_handler.publish(Event::Log(3, "Hello".to_owned()));
+    event_handler.publish(Event::Shutdown);
+    block_on(event_handler.publish_async(Event::Restart));
+
+    match block_on(logs.next()) {
+        Some(Event::Log(3, msg)) => assert_eq!(msg, "Hello"),
+        other => panic!("Unexpected event {:?}", other)
+    }
+    assert!(logs.try_next().is_err());
+
+    assert!(matches!(block_on(control.next()), Some(Event::Shutdown)));
+    assert!(matches!(block_on(control.next()), Some(Event::Restart)));
+    assert!(matches!(config.try_recv(), Ok(Event::Shutdown)));
+    assert!(matches!(config.try_recv(), Ok(Event::Restart)));
+    assert!(config.try_recv().is_err());
+
+    // Dropped subscribers are removed.
+    drop(logs);
+    drop(config);
+    event_handler.publish(Event::Shutdown);
+    event_handler.publish(Event::Log(3, "Bye".to_owned()));
+    assert_eq!(event_handler.subscribers.lock().unwrap().len(), 1);
+}
+
+#[test]
+pub fn test_event_backpressure() {
+    let event_handler = EventHandler::new();
+    let events = event_handler.subscribe_sync(&[Topic::MessageStatus, Topic::Log]);
+    let report = |i: usize| Event::MessageStatus(StatusReport::new(
+        &i.to_string(), crate::message::Status::Expired
+    ));
+
+    // Log events that do not fit into the queue are dropped.
+    for _ in 0..CHANNEL_CAPACITY + 10 {
+        event_handler.publish(Event::Log(3, "Hello".to_owned()));
+    }
+
+    // Publishing the status reports waits for room in the queue.
+    let publisher = event_handler.clone();
+    let publishing = thread::spawn(move || {
+        for i in 0..10 {
+            publisher.publish(report(i));
         }
     });
+    thread::sleep(Duration::from_millis(200));
+    assert!(!publishing.is_finished());
+
+    let (mut reports, mut logs) = (Vec::new(), 0);
+    while let Ok(event) = events.recv_timeout(Duration::from_millis(200)) {
+        match event {
+            Event::MessageStatus(report) => reports.push(report.id),
+            Event::Log(_, _) => logs += 1,
+            other => panic!("Unexpected event {:?}", other)
+        }
+    }
+    publishing.join().unwrap();
 
-    EventHandler(event_tx)
+    let expected: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+    assert_eq!(reports, expected);
+    assert_eq!(logs, CHANNEL_CAPACITY);
 }
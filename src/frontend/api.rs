@@ -0,0 +1,297 @@
+use std::net::IpAddr;
+
+use hyper::{self, Body, Method, Request, Response, StatusCode};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use crate::config::{self, Config, FieldError};
+use crate::event::{Event, EventHandler};
+use crate::frontend::auth::{self, Identity, LoginError, NewApiKey, Permission,
+                            Rejection};
+use crate::message::Message;
+use crate::queue;
+use crate::shutdown;
+use crate::telemetry;
+use crate::timeslots::TimeSlot;
+
+pub const PREFIX: &str = "/api/v1";
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let data = serde_json::to_vec(value).unwrap();
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(data))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, error: &str) -> Response<Body> {
+    json_response(status, &json!({ "error": error }))
+}
+
+fn accepted() -> Response<Body> {
+    json_response(StatusCode::ACCEPTED, &json!({ "ok": true }))
+}
+
+/// Authenticate a request by its `Authorization: Bearer` header, which
+/// carries either a session token or an API key.
+pub fn authenticate(req: &Request<Body>, ip: IpAddr) -> Result<Identity, LoginError> {
+    let token = req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+
+    let identity = auth::login_bearer(ip, token.trim())?;
+    auth::audit_request(ip, &identity);
+    Ok(identity)
+}
+
+fn unauthorized() -> Response<Body> {
+    let mut res = error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+    res.headers_mut().insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+    res
+}
+
+fn throttled() -> Response<Body> {
+    error_response(StatusCode::TOO_MANY_REQUESTS, "Too many failed login attempts")
+}
+
+#[derive(Deserialize)]
+struct Login {
+    password: String
+}
+
+// Exchange the password for a session token to be used as bearer token.
+async fn login(req: Request<Body>, ip: IpAddr) -> Response<Body> {
+    let login = match parse_body::<Login>(req).await {
+        Ok(login) => login,
+        Err(res) => return res
+    };
+
+    match auth::login(ip, &login.password) {
+        Ok(identity) => {
+            auth::audit(ip, &format!("API login as {}", identity.name()));
+            json_response(StatusCode::OK, &json!({
+                "session": auth::create_session(identity)
+            }))
+        }
+        Err(LoginError::Throttled) => throttled(),
+        Err(LoginError::Invalid) => unauthorized()
+    }
+}
+
+fn permission(method: &Method, path: &str) -> Permission {
+    match (method, path) {
+        (&Method::POST, "/message") => Permission::Send,
+        (&Method::GET, "/version") | (&Method::GET, "/telemetry") |
+        (&Method::GET, "/timeslot") | (&Method::GET, "/queue") => {
+            Permission::Read
+        }
+        _ => Permission::Admin
+    }
+}
+
+/// Check and publish a received message, shared with the legacy endpoint.
+pub async fn send_message(
+    identity: &Identity,
+    msg: Message,
+    event_handler: &EventHandler
+) -> Result<(), Response<Body>> {
+    match auth::check_message(identity, &msg) {
+        Ok(()) => {
+            event_handler.publish_async(Event::MessageReceived(msg)).await;
+            Ok(())
+        }
+        Err(rejection) => {
+            warn!("Message from {} rejected: {}", identity.name(), rejection);
+            let status = match rejection {
+                Rejection::Forbidden(_) => StatusCode::FORBIDDEN,
+                Rejection::RateLimited => StatusCode::TOO_MANY_REQUESTS
+            };
+            Err(error_response(status, &rejection.to_string()))
+        }
+    }
+}
+
+async fn parse_body<T: DeserializeOwned>(req: Request<Body>) -> Result<T, Response<Body>> {
+    let body = hyper::body::to_bytes(req.into_body()).await.map_err(|_| {
+        error_response(StatusCode::BAD_REQUEST, "Failed to read request body")
+    })?;
+
+    serde_json::from_slice(&body).map_err(|err| {
+        error_response(StatusCode::UNPROCESSABLE_ENTITY, &err.to_string())
+    })
+}
+
+fn invalid_config(errors: Vec<FieldError>) -> Response<Body> {
+    json_response(StatusCode::UNPROCESSABLE_ENTITY, &json!({
+        "error": "Invalid configuration",
+        "fields": errors
+    }))
+}
+
+async fn set_config(config: Config, event_handler: &EventHandler) -> Response<Body> {
+    match config::set(&config) {
+        Ok(config) => {
+            event_handler.publish_async(Event::ConfigUpdate(config.clone())).await;
+            json_response(StatusCode::OK, &config.redacted())
+        }
+        Err(errors) => invalid_config(errors)
+    }
+}
+
+pub async fn handle(
+    req: Request<Body>,
+    ip: IpAddr,
+    event_handler: EventHandler
+) -> Response<Body> {
+    let path = req.uri().path()[PREFIX.len()..].trim_end_matches('/').to_owned();
+    let method = req.method().clone();
+
+    if (&method, &*path) == (&Method::GET, "/openapi.json") {
+        return Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(&include_bytes!("assets/openapi.json")[..]))
+            .unwrap();
+    }
+    if (&method, &*path) == (&Method::POST, "/login") {
+        return login(req, ip).await;
+    }
+
+    let identity = match authenticate(&req, ip) {
+        Ok(identity) => identity,
+        Err(LoginError::Throttled) => return throttled(),
+        Err(LoginError::Invalid) => return unauthorized()
+    };
+
+    if !identity.allows(permission(&method, &path)) {
+        return error_response(StatusCode::FORBIDDEN, "Permission denied");
+    }
+
+    match (&method, &*path) {
+        (&Method::GET, "/version") => {
+            json_response(StatusCode::OK, &json!({
+                "version": env!("CARGO_PKG_VERSION")
+            }))
+        }
+        (&Method::GET, "/config") => {
+            json_response(StatusCode::OK, &config::get().redacted())
+        }
+        (&Method::PUT, "/config") => {
+            match parse_body::<Config>(req).await {
+                Ok(config) => set_config(config, &event_handler).await,
+                Err(res) => res
+            }
+        }
+        (&Method::POST, "/config/validate") => {
+            match parse_body::<Config>(req).await {
+                Ok(config) => match config.validate() {
+                    Ok(()) => {
+                        json_response(StatusCode::OK, &json!({ "valid": true }))
+                    }
+                    Err(errors) => invalid_config(errors)
+                },
+                Err(res) => res
+            }
+        }
+        (&Method::POST, "/config/default") => {
+            set_config(config::defaults(), &event_handler).await
+        }
+        (&Method::GET, "/config/history") => {
+            json_response(StatusCode::OK, &config::history())
+        }
+        (&Method::POST, path) if path.starts_with("/config/history/") &&
+                                 path.ends_with("/rollback") => {
+            let id = &path["/config/history/".len()..path.len() - "/rollback".len()];
+            if !config::history().iter().any(|version| version.id == id) {
+                return error_response(StatusCode::NOT_FOUND, "Not found");
+            }
+
+            match config::rollback(id) {
+                Ok(config) => {
+                    info!("Config rolled back to version {}.", id);
+                    let event = Event::ConfigUpdate(config.clone());
+                    event_handler.publish_async(event).await;
+                    json_response(StatusCode::OK, &config.redacted())
+                }
+                Err(err) => error_response(StatusCode::UNPROCESSABLE_ENTITY, &err)
+            }
+        }
+        (&Method::GET, "/telemetry") => {
+            json_response(StatusCode::OK, &telemetry::get())
+        }
+        (&Method::GET, "/timeslot") => {
+            json_response(StatusCode::OK, &json!({
+                "timeslot": TimeSlot::current()
+            }))
+        }
+        (&Method::GET, "/queue") => {
+            json_response(StatusCode::OK, &queue::get())
+        }
+        (&Method::POST, "/message") => {
+            match parse_body::<Message>(req).await {
+                Ok(msg) => {
+                    match send_message(&identity, msg, &event_handler).await {
+                        Ok(()) => accepted(),
+                        Err(res) => res
+                    }
+                }
+                Err(res) => res
+            }
+        }
+        (&Method::GET, "/keys") => {
+            json_response(StatusCode::OK, &auth::list_keys())
+        }
+        (&Method::POST, "/keys") => {
+            let new_key = match parse_body::<NewApiKey>(req).await {
+                Ok(new_key) => new_key,
+                Err(res) => return res
+            };
+            let name = new_key.name.clone();
+
+            match auth::create_key(new_key) {
+                Ok(key) => {
+                    info!("API key {} created.", name);
+                    json_response(StatusCode::CREATED, &json!({
+                        "name": name,
+                        "key": key
+                    }))
+                }
+                Err(err) => error_response(StatusCode::UNPROCESSABLE_ENTITY, &err)
+            }
+        }
+        (&Method::DELETE, path) if path.starts_with("/keys/") => {
+            let name = &path["/keys/".len()..];
+            if auth::delete_key(name) {
+                info!("API key {} deleted.", name);
+                json_response(StatusCode::OK, &json!({ "ok": true }))
+            }
+            else {
+                error_response(StatusCode::NOT_FOUND, "Not found")
+            }
+        }
+        (&Method::POST, "/restart") => {
+            event_handler.publish_async(Event::Restart).await;
+            accepted()
+        }
+        (&Method::POST, "/shutdown") => {
+            shutdown::request(&event_handler).await;
+            accepted()
+        }
+        (&Method::POST, "/test") => {
+            info!("Initiating test procedure...");
+            event_handler.publish_async(Event::Test).await;
+            accepted()
+        }
+        (_, "/version") | (_, "/config") | (_, "/config/validate") |
+        (_, "/config/default") | (_, "/config/history") | (_, "/telemetry") |
+        (_, "/timeslot") | (_, "/queue") | (_, "/message") | (_, "/restart") |
+        (_, "/shutdown") | (_, "/test") | (_, "/keys") | (_, "/login") => {
+            error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Not found")
+    }
+}
@@ -37,8 +37,24 @@ var vm = new Vue({
             }
         },
         auth: false,
-        password: localStorage ? (localStorage.password || null) : null,
-        messages: []
+        password: "",
+        session: localStorage ? (localStorage.session || null) : null,
+        password_change: {
+            old: "",
+            new: "",
+            repeat: ""
+        },
+        messages: [],
+        api_keys: [],
+        api_key: {
+            name: "",
+            scope: "send",
+            rate_limit: 0,
+            rics: ""
+        },
+        new_api_key: null,
+        config_errors: [],
+        config_history: []
     },
     watch: {
         config: {
@@ -57,7 +73,8 @@ var vm = new Vue({
     methods: {
         connect: function(event) {
             console.log("Connecting to the websocket.");
-            this.socket = new WebSocket("ws://" + location.hostname + ":8055");
+            var protocol = location.protocol === "https:" ? "wss://" : "ws://";
+            this.socket = new WebSocket(protocol + location.host + "/ws");
             this.socket.onopen = this.onopen;
             this.socket.onmessage = this.onmessage;
             this.socket.onclose = this.onclose;
@@ -66,7 +83,12 @@ var vm = new Vue({
         onopen: function(event) {
             this.connected = true;
             this.log.unshift({msg: "Connected to UniPager.", time: new Date()});
-            this.send({Authenticate: this.password || ""});
+            if (this.session) {
+                this.send({ResumeSession: this.session});
+            }
+            else {
+                this.send({Authenticate: ""});
+            }
         },
         onmessage: function(event) {
             var response = JSON.parse(event.data) || {};
@@ -76,6 +98,8 @@ var vm = new Vue({
                     case "Log": this.log_add(value); break;
                     case "Version": this.version = value; break;
                     case "Config": this.config = value; break;
+                    case "ConfigInvalid": this.config_invalid(value); break;
+                    case "ConfigHistory": this.config_history = value; break;
                     case "Telemetry": this.telemetry = value; break;
                     case "TelemetryUpdate": {
                         for (key in value) {
@@ -85,7 +109,13 @@ var vm = new Vue({
                     }
                     case "Timeslot": this.timeslot = value; break;
                     case "Authenticated": this.authenticated(value); break;
+                    case "Session": this.session_started(value); break;
+                    case "PasswordChanged": this.log_add([3, "Password changed."]); break;
                     case "Message": this.message_add(value); break;
+                    case "MessageStatus": this.message_status(value); break;
+                    case "ApiKeys": this.api_keys = value; break;
+                    case "ApiKeyCreated": this.new_api_key = value; break;
+                    case "Error": this.log_add([1, value]); break;
                     default: console.log("Unknown Key: ", key);
                 }
             }
@@ -126,14 +156,27 @@ var vm = new Vue({
             this.messages.unshift(message);
             this.messages = this.messages.slice(0, 50);
         },
+        message_status: function(report) {
+            this.messages.forEach(function(message) {
+                if (message.id === report.id) {
+                    Vue.set(message, "status", report.status);
+                }
+            });
+        },
         save_config: function(event) {
             if (this.config) {
+                this.config_errors = [];
                 this.send({"SetConfig": this.config});
             }
         },
         default_config: function(event) {
+            this.config_errors = [];
             this.send("DefaultConfig");
         },
+        config_invalid: function(errors) {
+            this.config_errors = errors;
+            this.log_add([1, "Config not saved, " + errors.length + " invalid field(s)."]);
+        },
         send_message: function(event) {
             localStorage && (localStorage.ric = this.message.message.ric);
             this.send({"SendMessage": this.message});
@@ -147,11 +190,34 @@ var vm = new Vue({
         shutdown: function(event) {
             this.send("Shutdown");
         },
+        create_api_key: function(event) {
+            var rics = this.api_key.rics.split(",").map(function(range) {
+                var parts = range.split("-");
+                var from = parseInt(parts[0]);
+                var to = parts.length > 1 ? parseInt(parts[1]) : from;
+                return {from: from, to: to};
+            }).filter(function(range) {
+                return !isNaN(range.from) && !isNaN(range.to);
+            });
+
+            this.send({"CreateApiKey": {
+                name: this.api_key.name,
+                scope: this.api_key.scope,
+                rate_limit: this.api_key.rate_limit || 0,
+                rics: rics
+            }});
+        },
+        delete_api_key: function(name) {
+            this.send({"DeleteApiKey": name});
+        },
+        rollback_config: function(id) {
+            if (confirm("Restore the config from " + id + "?")) {
+                this.send({"RollbackConfig": id});
+            }
+        },
         authenticate: function(event) {
             this.send({"Authenticate": this.password});
-            if (localStorage) {
-                localStorage.password = this.password;
-            }
+            this.password = "";
         },
         authenticated: function(auth) {
             this.auth = auth;
@@ -160,10 +226,41 @@ var vm = new Vue({
                 this.send("GetConfig");
                 this.send("GetTelemetry");
                 this.send("GetTimeslot");
+                this.send("GetApiKeys");
+                this.send("GetConfigHistory");
             }
-            else {
-                this.password = "";
+            else if (this.session) {
+                // The session has expired, try without a password.
+                this.session_started(null);
+                this.send({Authenticate: ""});
+            }
+        },
+        session_started: function(session) {
+            this.session = session;
+            if (localStorage) {
+                delete localStorage.password;
+                if (session) {
+                    localStorage.session = session;
+                }
+                else {
+                    delete localStorage.session;
+                }
+            }
+        },
+        logout: function(event) {
+            this.session_started(null);
+            this.send("Logout");
+        },
+        change_password: function(event) {
+            if (this.password_change.new !== this.password_change.repeat) {
+                this.log_add([1, "The new passwords do not match."]);
+                return;
             }
+            this.send({"ChangePassword": {
+                old: this.password_change.old,
+                new: this.password_change.new
+            }});
+            this.password_change = {old: "", new: "", repeat: ""};
         }
     }
 });
@@ -0,0 +1,438 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::config::{self, ApiKey, ApiKeyScope, RicRange};
+use crate::frontend::password;
+use crate::message::{Message, ProtocolMessage};
+
+// Stored next to the config file
+const AUDIT_FILE: &'static str = "audit.log";
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_FAILED_LOGINS: u32 = 5;
+const LOGIN_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+// Requests authenticated with the same credentials are audited once per hour.
+const AUDIT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    // Send times of the last messages per API key name.
+    static ref SENT: Mutex<HashMap<String, VecDeque<Instant>>> =
+        Mutex::new(HashMap::new());
+    // Sessions by token with the time they were last used.
+    static ref SESSIONS: Mutex<HashMap<String, (Identity, Instant)>> =
+        Mutex::new(HashMap::new());
+    // Failed logins per IP with the time of the last failure.
+    static ref FAILED_LOGINS: Mutex<HashMap<IpAddr, (u32, Instant)>> =
+        Mutex::new(HashMap::new());
+    // Last audit of a request login per IP and identity.
+    static ref AUDITED: Mutex<HashMap<(IpAddr, String), Instant>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    Read,
+    Send,
+    Admin
+}
+
+/// Who is making a request.
+#[derive(Clone, Debug)]
+pub enum Identity {
+    // Authenticated with the password file or no password is set.
+    Password,
+    Key(ApiKey)
+}
+
+impl Identity {
+    pub fn name(&self) -> &str {
+        match self {
+            Identity::Password => "password",
+            Identity::Key(key) => &key.name
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        let scope = match self {
+            Identity::Password => return true,
+            Identity::Key(key) => key.scope
+        };
+
+        match (scope, permission) {
+            (ApiKeyScope::Admin, _) => true,
+            (ApiKeyScope::Send, Permission::Send) => true,
+            (ApiKeyScope::Read, Permission::Read) => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    Forbidden(String),
+    RateLimited
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Forbidden(reason) => write!(f, "{}", reason),
+            Rejection::RateLimited => write!(f, "Rate limit exceeded")
+        }
+    }
+}
+
+/// Parameters of an API key to be created.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scope: ApiKeyScope,
+    #[serde(default)]
+    pub rate_limit: u32,
+    #[serde(default)]
+    pub rics: Vec<RicRange>
+}
+
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn generate_key() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginError {
+    Invalid,
+    Throttled
+}
+
+/// Append an entry for an authentication event to the audit log.
+pub fn audit(ip: IpAddr, event: &str) {
+    info!("Audit: {} from {}", event, ip);
+
+    let line = format!("{} {} {}\n", Utc::now().to_rfc3339(), ip, event);
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config::path().with_file_name(AUDIT_FILE))
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+
+    if let Err(err) = result {
+        error!("Failed to write audit log: {}", err);
+    }
+}
+
+/// Audit a successful login of a request. Clients that authenticate every
+/// request are only recorded once per hour.
+pub fn audit_request(ip: IpAddr, identity: &Identity) {
+    let now = Instant::now();
+    let mut audited = AUDITED.lock().unwrap();
+    audited.retain(|_, &mut last| now - last < AUDIT_INTERVAL);
+
+    let key = (ip, identity.name().to_owned());
+    if !audited.contains_key(&key) {
+        audited.insert(key, now);
+        audit(ip, &format!("API login as {}", identity.name()));
+    }
+}
+
+/// Authenticate a token, which may either be a session token, an API key or
+/// the password. Without a password and API keys everyone is allowed.
+pub fn authenticate(token: &str) -> Option<Identity> {
+    authenticate_bearer(token).or_else(|| {
+        if password::is_set() && password::verify(token) {
+            Some(Identity::Password)
+        }
+        else {
+            None
+        }
+    })
+}
+
+/// Authenticate a bearer token of the REST API, which is either a session
+/// token or an API key. The password is only accepted on login, so it is not
+/// hashed for every request.
+pub fn authenticate_bearer(token: &str) -> Option<Identity> {
+    if let Some(identity) = resume_session(token) {
+        return Some(identity);
+    }
+
+    let keys = config::get().api_keys;
+    let open = keys.is_empty() && !password::is_set();
+
+    let hash = hash_key(token);
+    if let Some(key) = keys.into_iter().find(|key| key.hash == hash) {
+        return Some(Identity::Key(key));
+    }
+
+    if open { Some(Identity::Password) } else { None }
+}
+
+fn is_throttled(ip: IpAddr) -> bool {
+    FAILED_LOGINS.lock().unwrap()
+        .get(&ip)
+        .map(|&(count, last)| {
+            count >= MAX_FAILED_LOGINS && last.elapsed() < LOGIN_LOCKOUT
+        })
+        .unwrap_or(false)
+}
+
+fn record_failed_login(ip: IpAddr) {
+    let now = Instant::now();
+    let mut failed = FAILED_LOGINS.lock().unwrap();
+    let entry = failed.entry(ip).or_insert((0, now));
+
+    if now - entry.1 >= LOGIN_LOCKOUT {
+        entry.0 = 0;
+    }
+
+    entry.0 += 1;
+    entry.1 = now;
+}
+
+/// Authenticate a token from a client. Repeated failures from the same IP
+/// block further attempts for some time.
+pub fn login(ip: IpAddr, token: &str) -> Result<Identity, LoginError> {
+    login_with(ip, token, authenticate)
+}
+
+/// Authenticate a bearer token of a request, throttled like `login`.
+pub fn login_bearer(ip: IpAddr, token: &str) -> Result<Identity, LoginError> {
+    login_with(ip, token, authenticate_bearer)
+}
+
+fn login_with(
+    ip: IpAddr,
+    token: &str,
+    authenticate: fn(&str) -> Option<Identity>
+) -> Result<Identity, LoginError> {
+    if is_throttled(ip) {
+        audit(ip, "login throttled");
+        return Err(LoginError::Throttled);
+    }
+
+    match authenticate(token) {
+        Some(identity) => {
+            FAILED_LOGINS.lock().unwrap().remove(&ip);
+            Ok(identity)
+        }
+        // An empty token is only sent to check whether a password is set.
+        None if token.is_empty() => Err(LoginError::Invalid),
+        None => {
+            record_failed_login(ip);
+            audit(ip, "login failed");
+            Err(LoginError::Invalid)
+        }
+    }
+}
+
+/// Create a session for the identity and return its token.
+pub fn create_session(identity: Identity) -> String {
+    let token = generate_key();
+    let mut sessions = SESSIONS.lock().unwrap();
+    sessions.retain(|_, &mut (_, used)| used.elapsed() < SESSION_TIMEOUT);
+    sessions.insert(token.clone(), (identity, Instant::now()));
+    token
+}
+
+/// Return the identity of a session that has not timed out yet.
+pub fn resume_session(token: &str) -> Option<Identity> {
+    let mut sessions = SESSIONS.lock().unwrap();
+
+    match sessions.get_mut(token) {
+        Some((identity, used)) if used.elapsed() < SESSION_TIMEOUT => {
+            *used = Instant::now();
+            Some(identity.clone())
+        }
+        Some(_) => {
+            sessions.remove(token);
+            None
+        }
+        None => None
+    }
+}
+
+pub fn end_session(token: &str) {
+    SESSIONS.lock().unwrap().remove(token);
+}
+
+/// Change the password and end all sessions that used the old one.
+pub fn change_password(old: &str, new: &str) -> Result<(), String> {
+    if !password::verify(old) {
+        return Err("Wrong password".to_owned());
+    }
+
+    password::set(new)
+        .map_err(|err| format!("Failed to store password: {}", err))?;
+
+    SESSIONS.lock().unwrap().retain(|_, (identity, _)| {
+        match identity {
+            Identity::Password => false,
+            Identity::Key(_) => true
+        }
+    });
+
+    Ok(())
+}
+
+/// Check whether the identity may send the message and account for it
+/// in the rate limit of the key.
+pub fn check_message(identity: &Identity, msg: &Message) -> Result<(), Rejection> {
+    let key = match identity {
+        Identity::Password => return Ok(()),
+        Identity::Key(key) => key
+    };
+
+    if !identity.allows(Permission::Send) {
+        return Err(Rejection::Forbidden(
+            "API key is not allowed to send messages".to_owned()
+        ));
+    }
+
+    let ric = match msg.message {
+        ProtocolMessage::Pocsag(ref msg) => msg.ric
+    };
+
+    if !key.rics.is_empty() && !key.rics.iter().any(|range| range.contains(ric)) {
+        return Err(Rejection::Forbidden(
+            format!("API key is not allowed to send to RIC {}", ric)
+        ));
+    }
+
+    if key.rate_limit > 0 {
+        let now = Instant::now();
+        let mut sent = SENT.lock().unwrap();
+        let times = sent.entry(key.name.clone()).or_insert_with(VecDeque::new);
+
+        while times.front().map(|&time| now - time >= RATE_LIMIT_WINDOW)
+            .unwrap_or(false) {
+            times.pop_front();
+        }
+
+        if times.len() >= key.rate_limit as usize {
+            return Err(Rejection::RateLimited);
+        }
+
+        times.push_back(now);
+    }
+
+    Ok(())
+}
+
+/// Create an API key and store its hash in the config. The key itself is
+/// returned and cannot be retrieved later.
+pub fn create_key(new_key: NewApiKey) -> Result<String, String> {
+    if new_key.name.is_empty() {
+        return Err("API key name must not be empty".to_owned());
+    }
+
+    if new_key.rics.iter().any(|range| range.from > range.to) {
+        return Err("Invalid RIC range".to_owned());
+    }
+
+    let mut config = config::get();
+
+    if config.api_keys.iter().any(|key| key.name == new_key.name) {
+        return Err(format!("API key {} already exists", new_key.name));
+    }
+
+    let key = generate_key();
+
+    config.api_keys.push(ApiKey {
+        name: new_key.name,
+        hash: hash_key(&key),
+        scope: new_key.scope,
+        rate_limit: new_key.rate_limit,
+        rics: new_key.rics
+    });
+    config::set(&config).map_err(|errors| {
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    })?;
+
+    Ok(key)
+}
+
+/// Delete an API key and return whether it existed.
+pub fn delete_key(name: &str) -> bool {
+    let mut config = config::get();
+    let count = config.api_keys.len();
+    config.api_keys.retain(|key| key.name != name);
+
+    if config.api_keys.len() == count {
+        return false;
+    }
+
+    if let Err(errors) = config::set(&config) {
+        error!("Failed to delete API key {}: {:?}", name, errors);
+        return false;
+    }
+
+    SENT.lock().unwrap().remove(name);
+    SESSIONS.lock().unwrap().retain(|_, (identity, _)| {
+        match identity {
+            Identity::Password => true,
+            Identity::Key(key) => key.name != name
+        }
+    });
+    true
+}
+
+/// List the API keys without their hashes.
+pub fn list_keys() -> Vec<ApiKey> {
+    config::get()
+        .api_keys
+        .into_iter()
+        .map(|key| ApiKey { hash: String::new(), ..key })
+        .collect()
+}
+
+#[test]
+pub fn test_check_message() {
+    use crate::pocsag;
+
+    let key = ApiKey {
+        name: "test_check_message".to_owned(),
+        hash: hash_key("secret"),
+        scope: ApiKeyScope::Send,
+        rate_limit: 2,
+        rics: vec![RicRange { from: 100, to: 200 }]
+    };
+    let identity = Identity::Key(key);
+
+    let message = |ric| Message {
+        id: String::new(),
+        priority: 3,
+        origin: String::new(),
+        expires_on: None,
+        message: ProtocolMessage::Pocsag(pocsag::Message {
+            ric,
+            ..pocsag::Message::default()
+        })
+    };
+
+    assert!(identity.allows(Permission::Send));
+    assert!(!identity.allows(Permission::Admin));
+    assert!(check_message(&identity, &message(300)).is_err());
+    assert_eq!(check_message(&identity, &message(150)), Ok(()));
+    assert_eq!(check_message(&identity, &message(200)), Ok(()));
+    assert_eq!(
+        check_message(&identity, &message(100)),
+        Err(Rejection::RateLimited)
+    );
+    assert_eq!(check_message(&Identity::Password, &message(300)), Ok(()));
+}
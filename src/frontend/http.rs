@@ -1,14 +1,22 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use bytes::buf::Buf;
 
 use serde_json;
+use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio_rustls::TlsAcceptor;
 
 use hyper::{self, Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::HeaderMap;
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
 
-use crate::event::{Event, EventHandler};
+use crate::config::{self, FrontendConfig};
+use crate::event::EventHandler;
+use crate::frontend::api;
+use crate::frontend::websocket::Websocket;
+use crate::metrics;
 use crate::telemetry;
 
 fn file_response(data: &[u8], content_type: &str) -> Response<Body> {
@@ -19,7 +27,36 @@ fn file_response(data: &[u8], content_type: &str) -> Response<Body> {
         .unwrap()
 }
 
-async fn response(req: Request<Body>, event_handler: EventHandler) -> Result<Response<Body>, hyper::Error> {
+// Address of the client. Behind a trusted reverse proxy it is the last entry
+// of X-Forwarded-For, which the proxy appended.
+fn client_addr(headers: &HeaderMap, peer: SocketAddr, trusted: &[String]) -> SocketAddr {
+    if !trusted.iter().any(|proxy| proxy.parse() == Ok(peer.ip())) {
+        return peer;
+    }
+
+    headers.get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, peer.port()))
+        .unwrap_or(peer)
+}
+
+async fn response(req: Request<Body>, peer: SocketAddr, websocket: Websocket, event_handler: EventHandler) -> Result<Response<Body>, hyper::Error> {
+    let trusted = config::get().frontend.trusted_proxies;
+    let addr = client_addr(req.headers(), peer, &trusted);
+    let ip = addr.ip();
+
+    if req.uri().path().starts_with(api::PREFIX) {
+        return Ok(api::handle(req, ip, event_handler).await);
+    }
+
+    if (req.method(), req.uri().path()) == (&Method::GET, "/ws") {
+        return Ok(websocket.upgrade(req, addr));
+    }
+
     match (req.method(), req.uri().path())
     {
         (&Method::GET, "/") |
@@ -70,13 +107,44 @@ async fn response(req: Request<Body>, event_handler: EventHandler) -> Result<Res
                     .unwrap()
             )
         }
+        (&Method::GET, "/metrics") => {
+            Ok(file_response(
+                metrics::get().as_bytes(),
+                "text/plain; version=0.0.4"
+            ))
+        }
         (&Method::POST, "/message") => {
+            let identity = match api::authenticate(&req, ip) {
+                Ok(identity) => identity,
+                Err(_) => {
+                    let body = Body::from("{\"ok\": false}");
+                    return Ok(
+                        Response::builder()
+                            .status(StatusCode::UNAUTHORIZED)
+                            .body(body)
+                            .unwrap()
+                    );
+                }
+            };
+
             let body = hyper::body::to_bytes(req).await.unwrap();
 
             if let Ok(msg) = serde_json::from_slice(&body) {
-                event_handler.publish(Event::MessageReceived(msg));
-                let body = Body::from("{\"ok\": true}");
-                Ok(Response::builder().body(body).unwrap())
+                match api::send_message(&identity, msg, &event_handler).await {
+                    Ok(()) => {
+                        let body = Body::from("{\"ok\": true}");
+                        Ok(Response::builder().body(body).unwrap())
+                    }
+                    Err(res) => {
+                        let body = Body::from("{\"ok\": false}");
+                        Ok(
+                            Response::builder()
+                                .status(res.status())
+                                .body(body)
+                                .unwrap()
+                        )
+                    }
+                }
             }
             else {
                 let body = Body::from("{\"ok\": false}");
@@ -95,20 +163,101 @@ async fn response(req: Request<Body>, event_handler: EventHandler) -> Result<Res
     }
 }
 
-pub fn start(runtime: &Runtime, event_handler: EventHandler) {
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8073));
+async fn serve_tls(addr: SocketAddr, tls: TlsAcceptor, websocket: Websocket, event_handler: EventHandler) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to start HTTPS server on {}: {}", addr, err);
+            return;
+        }
+    };
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        let tls = tls.clone();
+        let websocket = websocket.clone();
+        let event_handler = event_handler.clone();
+
+        tokio::spawn(async move {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("TLS handshake with {} failed: {}", peer, err);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |req| {
+                response(req, peer, websocket.clone(), event_handler.clone())
+            });
+
+            Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+                .ok();
+        });
+    }
+}
+
+async fn serve(addr: SocketAddr, websocket: Websocket, event_handler: EventHandler) {
+    let builder = match Server::try_bind(&addr) {
+        Ok(builder) => builder,
+        Err(err) => {
+            error!("Failed to start HTTP server on {}: {}", addr, err);
+            return;
+        }
+    };
 
-    let new_service = make_service_fn(move |_| {
+    let new_service = make_service_fn(move |conn: &AddrStream| {
+        let peer = conn.remote_addr();
+        let websocket2 = websocket.clone();
         let event_handler2 = event_handler.clone();
-        async {
+        async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
+                let websocket3 = websocket2.clone();
                 let event_handler3 = event_handler2.clone();
-                response(req, event_handler3)
+                response(req, peer, websocket3, event_handler3)
             }))
         }
     });
 
-    runtime.spawn(async move {
-        Server::bind(&addr).serve(new_service).await;
-    });
+    builder.serve(new_service).await;
+}
+
+pub fn start(runtime: &Runtime, config: &FrontendConfig, tls: Option<TlsAcceptor>, websocket: Websocket, event_handler: EventHandler) {
+    for addr in &config.http {
+        let addr = match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                error!("Invalid HTTP listen address {}", addr);
+                continue;
+            }
+        };
+
+        let websocket = websocket.clone();
+        let event_handler = event_handler.clone();
+
+        match tls {
+            Some(ref tls) => {
+                runtime.spawn(serve_tls(addr, tls.clone(), websocket, event_handler));
+            }
+            None => {
+                runtime.spawn(serve(addr, websocket, event_handler));
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_client_addr() {
+    let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", "10.0.0.1, 192.0.2.7".parse().unwrap());
+
+    // Only the entry added by the trusted proxy is used.
+    let trusted = vec![String::from("127.0.0.1")];
+    assert_eq!(client_addr(&headers, peer, &trusted),
+               "192.0.2.7:4000".parse().unwrap());
+    assert_eq!(client_addr(&headers, peer, &[]), peer);
+    assert_eq!(client_addr(&HeaderMap::new(), peer, &trusted), peer);
 }
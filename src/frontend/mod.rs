@@ -1,10 +1,16 @@
+pub mod api;
+pub mod auth;
 pub mod http;
+pub mod password;
+pub mod snpp;
+pub mod tls;
 pub mod websocket;
 
 use serde_json;
 
-use crate::config::Config;
-use crate::message::Message;
+use crate::config::{ApiKey, Config, ConfigVersion, FieldError};
+use crate::frontend::auth::NewApiKey;
+use crate::message::{Message, StatusReport};
 use crate::telemetry::Telemetry;
 use crate::timeslots::TimeSlot;
 
@@ -14,10 +20,19 @@ pub enum Request {
     DefaultConfig,
     SendMessage(Message),
     Authenticate(String),
+    ResumeSession(String),
+    Logout,
+    ChangePassword { old: String, new: String },
     GetConfig,
+    GetConfigHistory,
+    RollbackConfig(String),
     GetTelemetry,
     GetTimeslot,
     GetVersion,
+    GetQueue,
+    GetApiKeys,
+    CreateApiKey(NewApiKey),
+    DeleteApiKey(String),
     Restart,
     Shutdown,
     Test
@@ -26,11 +41,20 @@ pub enum Request {
 #[derive(Debug, Serialize)]
 pub enum Response {
     Config(Config),
+    ConfigInvalid(Vec<FieldError>),
+    ConfigHistory(Vec<ConfigVersion>),
     Telemetry(Telemetry),
     TelemetryUpdate(serde_json::Value),
     Timeslot(TimeSlot),
     Version(String),
+    Queue(Vec<Message>),
     Message(Message),
+    MessageStatus(StatusReport),
     Log(u8, String),
-    Authenticated(bool)
+    Authenticated(bool),
+    Session(String),
+    PasswordChanged,
+    ApiKeys(Vec<ApiKey>),
+    ApiKeyCreated(String, String),
+    Error(String)
 }
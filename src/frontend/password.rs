@@ -0,0 +1,122 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier,
+                            SaltString};
+use rand::rngs::OsRng;
+
+const PASSWORD_FILE: &'static str = "password";
+
+lazy_static! {
+    static ref PATH: RwLock<PathBuf> = RwLock::new(PathBuf::from(PASSWORD_FILE));
+    // Argon2 hash of the password in PHC string format.
+    static ref PASSWORD: RwLock<Option<String>> = RwLock::new(load());
+}
+
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Failed to hash password")
+        .to_string()
+}
+
+pub fn verify_hash(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Set the location of the password file. Must be called before `init`.
+pub fn set_path(path: PathBuf) {
+    *PATH.write().unwrap() = path;
+}
+
+fn write(hash: &str) -> io::Result<()> {
+    let path = PATH.read().unwrap().clone();
+    let tmp_file = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_file)?;
+
+    file.write_all(hash.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(&tmp_file, &path)
+}
+
+// Load the password hash. A plaintext password file from older versions is
+// replaced by its hash.
+fn load() -> Option<String> {
+    let content = File::open(&*PATH.read().unwrap())
+        .and_then(|mut f| {
+            let mut s = String::new();
+            f.read_to_string(&mut s)?;
+            Ok(s)
+        })
+        .map(|s| s.trim().to_owned())
+        .map_err(|_| eprintln!("Failed to load password file."))
+        .ok()?;
+
+    if PasswordHash::new(&content).is_ok() {
+        return Some(content);
+    }
+
+    let hash = hash(&content);
+
+    match write(&hash) {
+        Ok(()) => eprintln!("Replaced plaintext password file with its hash."),
+        Err(err) => eprintln!("Failed to write hashed password file: {}", err)
+    }
+
+    Some(hash)
+}
+
+pub fn init() {
+    lazy_static::initialize(&PASSWORD);
+}
+
+/// Return the current password hash or `None` if no password is set.
+pub fn get() -> Option<String> {
+    PASSWORD.read().unwrap().clone()
+}
+
+pub fn is_set() -> bool {
+    PASSWORD.read().unwrap().is_some()
+}
+
+/// Check the password. Everything is accepted if no password is set.
+pub fn verify(password: &str) -> bool {
+    match get() {
+        Some(hash) => verify_hash(&hash, password),
+        None => true
+    }
+}
+
+/// Store the hash of a new password.
+pub fn set(password: &str) -> io::Result<()> {
+    let hash = hash(password);
+    write(&hash)?;
+    *PASSWORD.write().unwrap() = Some(hash);
+    Ok(())
+}
+
+#[test]
+pub fn test_password_hash() {
+    let hash = hash("secret");
+    assert!(hash.starts_with("$argon2"));
+    assert!(verify_hash(&hash, "secret"));
+    assert!(!verify_hash(&hash, "wrong"));
+    assert!(!verify_hash("secret", "secret"));
+}
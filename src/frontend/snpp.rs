@@ -0,0 +1,410 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::prelude::*;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+use crate::config::{Config, SnppConfig};
+use crate::event::{Event, EventHandler};
+use crate::frontend::auth::{self, Identity, LoginError};
+use crate::message::{Message, ProtocolMessage};
+use crate::pocsag;
+use crate::queue::NUM_PRIORITIES;
+
+// Longest accepted line and message
+const MAX_LINE: usize = 1024;
+const MAX_MESSAGE: usize = 4096;
+
+static MESSAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Map a SNPP service level (RFC 1861) to a message priority.
+fn level_priority(level: u8, default: usize) -> usize {
+    match level {
+        0 => NUM_PRIORITIES,
+        1 => default,
+        2..=3 => 2,
+        _ => 1,
+    }
+}
+
+// Parse the argument of a HOLD command: YYMMDDHHMMSS [+/-GMTdifference]
+fn parse_hold(args: &str) -> Option<DateTime<Utc>> {
+    let mut args = args.split_whitespace();
+    let time = NaiveDateTime::parse_from_str(args.next()?, "%y%m%d%H%M%S")
+        .ok()?;
+
+    let offset = match args.next() {
+        Some(diff) if diff.len() == 5 => {
+            let sign = match &diff[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let hours: i32 = diff[1..3].parse().ok()?;
+            let minutes: i32 = diff[3..5].parse().ok()?;
+            FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?
+        }
+        Some(_) => return None,
+        None => FixedOffset::east_opt(0)?,
+    };
+
+    offset
+        .from_local_datetime(&time)
+        .single()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+type Login = Box<dyn Fn(&str) -> Result<Identity, LoginError> + Send>;
+
+/// Line based SNPP protocol state machine.
+pub struct Session {
+    config: SnppConfig,
+    identity: Option<Identity>,
+    login: Login,
+    pagers: Vec<u32>,
+    data: Option<String>,
+    priority: usize,
+    expires_on: Option<DateTime<Utc>>,
+    // Lines of a multi-line DATA message
+    lines: Option<Vec<String>>,
+    // Whether the DATA message exceeded the size limit
+    too_long: bool
+}
+
+/// Result of a single line sent by the client.
+pub struct Reply {
+    pub response: String,
+    pub messages: Vec<Message>,
+    pub close: bool
+}
+
+impl Reply {
+    fn new(response: &str) -> Reply {
+        Reply {
+            response: response.to_owned(),
+            messages: Vec::new(),
+            close: false
+        }
+    }
+}
+
+impl Session {
+    /// `identity` is set if no login is needed, `login` checks the password
+    /// of LOGI.
+    pub fn new<F>(config: &SnppConfig, identity: Option<Identity>, login: F) -> Session
+        where F: Fn(&str) -> Result<Identity, LoginError> + Send + 'static
+    {
+        Session {
+            config: config.clone(),
+            identity,
+            login: Box::new(login),
+            pagers: Vec::new(),
+            data: None,
+            priority: config.priority,
+            expires_on: None,
+            lines: None,
+            too_long: false
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pagers.clear();
+        self.data = None;
+        self.priority = self.config.priority;
+        self.expires_on = None;
+    }
+
+    pub fn line(&mut self, line: &str) -> Reply {
+        if let Some(mut lines) = self.lines.take() {
+            if line == "." {
+                if self.too_long {
+                    self.too_long = false;
+                    return Reply::new("550 Error, Message Too Long");
+                }
+                self.data = Some(lines.join("\n"));
+                return Reply::new("250 Message OK");
+            }
+
+            // Remove dot stuffing
+            let line = line.strip_prefix('.').unwrap_or(line);
+            let size: usize = lines.iter().map(|line| line.len() + 1).sum();
+            if size + line.len() > MAX_MESSAGE {
+                self.too_long = true;
+            }
+            else {
+                lines.push(line.to_owned());
+            }
+            self.lines = Some(lines);
+            return Reply::new("");
+        }
+
+        let mut parts = line.trim().splitn(2, ' ');
+        let command = parts.next().unwrap_or("").to_ascii_uppercase();
+        let args = parts.next().unwrap_or("").trim();
+
+        // Commands may be abbreviated to their first four characters.
+        match command.get(..4).unwrap_or(&command) {
+            "LOGI" => {
+                let mut args = args.split_whitespace();
+                let _login = args.next();
+                let pass = args.next().unwrap_or("");
+
+                match (self.login)(pass) {
+                    Ok(identity) => {
+                        self.identity = Some(identity);
+                        Reply::new("250 Login Accepted")
+                    }
+                    Err(LoginError::Throttled) => {
+                        let mut reply =
+                            Reply::new("421 Too Many Failed Logins, Goodbye");
+                        reply.close = true;
+                        reply
+                    }
+                    Err(LoginError::Invalid) => {
+                        warn!("SNPP login with invalid password rejected.");
+                        Reply::new("550 Error, Invalid Login")
+                    }
+                }
+            }
+            "PAGE" => {
+                let pager = args.split_whitespace().next().unwrap_or("");
+                match pager.parse() {
+                    Ok(ric) => {
+                        self.pagers.push(ric);
+                        Reply::new("250 Pager ID Accepted")
+                    }
+                    Err(_) => Reply::new("550 Error, Invalid Pager ID")
+                }
+            }
+            "MESS" => {
+                if self.data.is_some() {
+                    Reply::new("503 Error, Message Already Entered")
+                }
+                else {
+                    self.data = Some(args.to_owned());
+                    Reply::new("250 Message OK")
+                }
+            }
+            "DATA" => {
+                if self.data.is_some() {
+                    Reply::new("503 Error, Message Already Entered")
+                }
+                else {
+                    self.lines = Some(Vec::new());
+                    Reply::new("354 Begin Input; End with <CRLF>'.'<CRLF>")
+                }
+            }
+            "LEVE" => {
+                match args.parse() {
+                    Ok(level) if level <= 11 => {
+                        self.priority =
+                            level_priority(level, self.config.priority);
+                        Reply::new("250 OK, Alert Override Accepted")
+                    }
+                    _ => Reply::new("550 Error, Invalid Service Level")
+                }
+            }
+            "HOLD" => {
+                match parse_hold(args) {
+                    Some(time) => {
+                        self.expires_on = Some(time);
+                        Reply::new("250 OK, Expiry Time Accepted")
+                    }
+                    None => Reply::new("550 Error, Invalid Delivery Date/Time")
+                }
+            }
+            "RESE" => {
+                self.reset();
+                Reply::new("250 RESET OK")
+            }
+            "SEND" => self.send(),
+            "QUIT" => {
+                let mut reply = Reply::new("221 OK, Goodbye");
+                reply.close = true;
+                reply
+            }
+            "HELP" => {
+                Reply::new("214 PAGE LOGI MESS DATA LEVE HOLD RESE SEND QUIT HELP\r\n\
+                            250 End of Help Information")
+            }
+            _ => Reply::new("500 Command Not Implemented")
+        }
+    }
+
+    fn send(&mut self) -> Reply {
+        let identity = match self.identity {
+            Some(ref identity) => identity.clone(),
+            None => return Reply::new("550 Error, Login Required")
+        };
+
+        let data = match self.data {
+            Some(ref data) if !self.pagers.is_empty() => data.clone(),
+            _ => return Reply::new("503 Error, Pager ID or Message Incomplete")
+        };
+
+        let messages = self.pagers.iter().map(|&ric| {
+            let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+            Message {
+                id: format!("snpp-{}-{}", Utc::now().timestamp(), counter),
+                priority: self.priority.max(1).min(NUM_PRIORITIES),
+                origin: "snpp".to_owned(),
+                expires_on: self.expires_on,
+                message: ProtocolMessage::Pocsag(pocsag::Message {
+                    ric,
+                    data: data.clone(),
+                    ..pocsag::Message::default()
+                })
+            }
+        }).collect::<Vec<Message>>();
+
+        for message in &messages {
+            if let Err(rejection) = auth::check_message(&identity, message) {
+                warn!("SNPP message from {} rejected: {}", identity.name(),
+                      rejection);
+                self.reset();
+                return Reply::new(&format!("550 Error, {}", rejection));
+            }
+        }
+
+        self.reset();
+
+        let mut reply = Reply::new("250 Message Sent Successfully");
+        reply.messages = messages;
+        reply
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    config: SnppConfig,
+    event_handler: EventHandler
+) -> io::Result<()> {
+    let ip = stream.peer_addr()?.ip();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    // Without a password and API keys no login is needed.
+    let mut session = Session::new(&config, auth::authenticate(""), move |pass| {
+        let identity = auth::login(ip, pass)?;
+        auth::audit(ip, &format!("SNPP login as {}", identity.name()));
+        Ok(identity)
+    });
+
+    writer.write_all(b"220 UniPager SNPP Gateway Ready\r\n").await?;
+
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        let read = (&mut reader)
+            .take(MAX_LINE as u64 + 1)
+            .read_until(b'\n', &mut buffer)
+            .await?;
+
+        if read == 0 {
+            break;
+        }
+
+        if !buffer.ends_with(b"\n") && buffer.len() > MAX_LINE {
+            writer.write_all(b"421 Line Too Long, Goodbye\r\n").await?;
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&buffer);
+        let reply = session.line(line.trim_end_matches(&['\r', '\n'][..]));
+
+        for msg in reply.messages {
+            info!("Message received via SNPP: {:?}", msg);
+            event_handler.publish_async(Event::MessageReceived(msg)).await;
+        }
+
+        if !reply.response.is_empty() {
+            writer.write_all(reply.response.as_bytes()).await?;
+            writer.write_all(b"\r\n").await?;
+        }
+
+        if reply.close {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn start(runtime: &Runtime, config: &Config, event_handler: EventHandler) {
+    if !config.snpp.enabled {
+        return;
+    }
+
+    let config = config.snpp.clone();
+
+    let addr: SocketAddr = match config.listen.parse() {
+        Ok(addr) => addr,
+        Err(_) => {
+            error!("Invalid SNPP listen address {}", config.listen);
+            return;
+        }
+    };
+
+    runtime.spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to start SNPP server on {}: {}", addr, err);
+                return;
+            }
+        };
+
+        info!("SNPP server listening on {}.", addr);
+
+        while let Ok((stream, peer)) = listener.accept().await {
+            let config = config.clone();
+            let event_handler = event_handler.clone();
+
+            tokio::spawn(async move {
+                if let Err(err) =
+                    handle_connection(stream, config, event_handler).await {
+                    warn!("SNPP connection from {} failed: {}", peer, err);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+pub fn test_snpp_session() {
+    let config = SnppConfig::default();
+    let mut session = Session::new(&config, None, |pass: &str| {
+        if pass == "secret" { Ok(Identity::Password) }
+        else { Err(LoginError::Invalid) }
+    });
+
+    assert!(session.line("PAGE 12345").response.starts_with("250"));
+    assert!(session.line("MESS Hello World").response.starts_with("250"));
+    assert!(session.line("SEND").response.starts_with("550"));
+    assert!(session.line("LOGI admin wrong").response.starts_with("550"));
+    assert!(session.line("LOGI admin secret").response.starts_with("250"));
+    assert!(session.line("LEVEL 0").response.starts_with("250"));
+    assert!(session.line("HOLD 300101120000 +0100").response.starts_with("250"));
+
+    let reply = session.line("SEND");
+    assert!(reply.response.starts_with("250"));
+    assert_eq!(reply.messages.len(), 1);
+    assert_eq!(reply.messages[0].priority, NUM_PRIORITIES);
+    assert_eq!(
+        reply.messages[0].expires_on,
+        "2030-01-01T11:00:00Z".parse::<DateTime<Utc>>().ok()
+    );
+
+    assert!(session.line("SEND").response.starts_with("503"));
+
+    assert!(session.line("DATA").response.starts_with("354"));
+    for _ in 0..MAX_MESSAGE / 100 + 1 {
+        session.line(&"x".repeat(100));
+    }
+    assert!(session.line(".").response.starts_with("550"));
+    assert!(session.line("QUIT").close);
+}
@@ -0,0 +1,88 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls_pemfile::Item;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+use crate::config::TlsConfig;
+
+fn invalid_data(error: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Generate a self-signed certificate for the hostname of this machine.
+fn generate_certificate(config: &TlsConfig) -> io::Result<()> {
+    let hostname = fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_owned())
+        .unwrap_or_else(|_| String::from("unipager"));
+
+    let names = vec![hostname, String::from("localhost")];
+    let cert = rcgen::generate_simple_self_signed(names)
+        .map_err(|err| invalid_data(err.to_string()))?;
+    let cert_pem = cert.serialize_pem()
+        .map_err(|err| invalid_data(err.to_string()))?;
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&config.key)?
+        .write_all(cert.serialize_private_key_pem().as_bytes())?;
+
+    File::create(&config.cert)?.write_all(cert_pem.as_bytes())
+}
+
+fn load_certificates(path: &str) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(format!("No certificate found in {}", path)));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    rustls_pemfile::read_all(&mut reader)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                Some(PrivateKey(key))
+            }
+            _ => None
+        })
+        .next()
+        .ok_or_else(|| invalid_data(format!("No private key found in {}", path)))
+}
+
+/// Create the TLS acceptor for the frontends or `None` if TLS is disabled.
+/// A self-signed certificate is created if the configured files are missing.
+pub fn acceptor(config: &TlsConfig) -> io::Result<Option<TlsAcceptor>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    if !Path::new(&config.cert).exists() && !Path::new(&config.key).exists() {
+        info!("Generating self-signed TLS certificate {}.", config.cert);
+        generate_certificate(config)?;
+    }
+
+    let certs = load_certificates(&config.cert)?;
+    let key = load_private_key(&config.key)?;
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid_data(err.to_string()))?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(server_config))))
+}
@@ -3,71 +3,243 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio_rustls::TlsAcceptor;
 use tokio;
 use serde_json;
 use futures;
-use tungstenite::protocol::Message;
-use futures_util::{StreamExt, TryStreamExt};
+use hyper::{self, Body, StatusCode};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use tokio_tungstenite::WebSocketStream;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::{Message, Role};
+use futures_util::StreamExt;
 
 use crate::frontend::{Request, Response};
-use crate::config;
-use crate::event::{self, Event, EventHandler};
+use crate::frontend::auth::{self, Identity, LoginError, Permission};
+use crate::config::{self, FrontendConfig};
+use crate::event::{Event, EventHandler, Topic};
+use crate::queue;
+use crate::shutdown;
 use crate::telemetry;
 use crate::timeslots::TimeSlot;
 
-type PeerMap = Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Response>>>>;
+type PeerMap = Arc<Mutex<HashMap<SocketAddr, Peer>>>;
+
+// Connection as seen by the broadcasts
+struct Peer {
+    tx: UnboundedSender<Response>,
+    // Broadcasts are only sent after the client logged in.
+    identity: Option<Identity>
+}
 
 struct Connection {
     tx: UnboundedSender<Response>,
-    password: Option<String>,
-    auth: bool,
+    addr: SocketAddr,
+    connections: PeerMap,
+    identity: Option<Identity>,
+    session: Option<String>,
     event_handler: EventHandler
 }
 
 impl Connection {
-    fn handle(&mut self, req: &Request) {
-        if let Request::Authenticate(ref pass) = req {
-            self.auth =
-                self.password.as_ref().map(|p| pass == p).unwrap_or(true);
+    async fn handle(&mut self, req: &Request) {
+        match req {
+            Request::Authenticate(token) => return self.authenticate(token),
+            Request::ResumeSession(token) => {
+                self.set_identity(auth::resume_session(token));
+                self.session = self.identity.as_ref().map(|_| token.clone());
 
-            let res = Response::Authenticated(self.auth);
-            self.tx.unbounded_send(res).ok();
+                let res = Response::Authenticated(self.identity.is_some());
+                self.tx.unbounded_send(res).ok();
+                return;
+            }
+            Request::Logout => {
+                if let Some(session) = self.session.take() {
+                    auth::end_session(&session);
+                }
+                if let Some(identity) = self.identity.take() {
+                    let event = format!("logout of {}", identity.name());
+                    auth::audit(self.addr.ip(), &event);
+                }
+                self.set_identity(None);
+
+                let res = Response::Authenticated(false);
+                self.tx.unbounded_send(res).ok();
+                return;
+            }
+            _ => {}
         }
-        else if self.auth {
-            self.handle_request(req);
+
+        let allowed = match self.identity {
+            Some(ref identity) => identity.allows(Self::permission(req)),
+            None => {
+                let res = Response::Authenticated(false);
+                self.tx.unbounded_send(res).ok();
+                return;
+            }
+        };
+
+        if allowed {
+            self.handle_request(req).await;
         }
         else {
-            let res = Response::Authenticated(false);
+            let res = Response::Error("Permission denied".to_owned());
             self.tx.unbounded_send(res).ok();
         }
     }
 
-    fn handle_request(&mut self, req: &Request) {
+    async fn set_config(&mut self, config: config::Config) {
+        match config::set(&config) {
+            Ok(config) => {
+                self.event_handler.publish_async(Event::ConfigUpdate(config)).await;
+                let res = Response::ConfigHistory(config::history());
+                self.tx.unbounded_send(res).ok();
+            }
+            Err(errors) => {
+                let res = Response::ConfigInvalid(errors);
+                self.tx.unbounded_send(res).ok();
+            }
+        }
+    }
+
+    fn authenticate(&mut self, token: &str) {
+        let ip = self.addr.ip();
+
+        let identity = match auth::login(ip, token) {
+            Ok(identity) => {
+                auth::audit(ip, &format!("login as {}", identity.name()));
+                Some(identity)
+            }
+            Err(LoginError::Throttled) => {
+                let res = Response::Error(
+                    "Too many failed login attempts".to_owned()
+                );
+                self.tx.unbounded_send(res).ok();
+                None
+            }
+            Err(LoginError::Invalid) => None
+        };
+        self.set_identity(identity);
+
+        self.start_session();
+
+        let res = Response::Authenticated(self.identity.is_some());
+        self.tx.unbounded_send(res).ok();
+    }
+
+    fn set_identity(&mut self, identity: Option<Identity>) {
+        if let Some(peer) = self.connections.lock().unwrap().get_mut(&self.addr) {
+            peer.identity = identity.clone();
+        }
+        self.identity = identity;
+    }
+
+    // Create a session the client can resume after reconnecting.
+    fn start_session(&mut self) {
+        self.session = self.identity.clone().map(auth::create_session);
+
+        if let Some(ref session) = self.session {
+            self.tx.unbounded_send(Response::Session(session.clone())).ok();
+        }
+    }
+
+    // Send the key list and the config, which contains the keys.
+    fn send_api_keys(&self) {
+        self.tx.unbounded_send(Response::ApiKeys(auth::list_keys())).ok();
+        self.tx.unbounded_send(Response::Config(config::get().redacted())).ok();
+    }
+
+    fn permission(req: &Request) -> Permission {
+        match req {
+            Request::SendMessage(_) => Permission::Send,
+            Request::GetTelemetry | Request::GetTimeslot |
+            Request::GetVersion | Request::GetQueue => Permission::Read,
+            _ => Permission::Admin
+        }
+    }
+
+    async fn handle_request(&mut self, req: &Request) {
         match req
         {
             Request::SetConfig(new_config) => {
-                let config = new_config.clone();
-                config::set(&config);
-                self.event_handler.publish(Event::ConfigUpdate(config));
+                self.set_config(new_config.clone()).await;
             }
             Request::DefaultConfig => {
-                let config = config::Config::default();
-                config::set(&config);
-                self.event_handler.publish(Event::ConfigUpdate(config));
+                self.set_config(config::defaults()).await;
             }
             Request::SendMessage(msg) => {
-                let msg = msg.clone();
-                self.event_handler.publish(Event::MessageReceived(msg));
+                let identity = self.identity.as_ref().unwrap();
+                match auth::check_message(identity, msg) {
+                    Ok(()) => {
+                        let event = Event::MessageReceived(msg.clone());
+                        self.event_handler.publish_async(event).await;
+                    }
+                    Err(rejection) => {
+                        warn!(
+                            "Message from {} rejected: {}",
+                            identity.name(),
+                            rejection
+                        );
+                        let res = Response::Error(rejection.to_string());
+                        self.tx.unbounded_send(res).ok();
+                    }
+                }
             }
             Request::GetConfig => {
-                self.tx.unbounded_send(Response::Config(config::get())).ok();
+                let config = config::get().redacted();
+                self.tx.unbounded_send(Response::Config(config)).ok();
+            }
+            Request::GetConfigHistory => {
+                let res = Response::ConfigHistory(config::history());
+                self.tx.unbounded_send(res).ok();
+            }
+            Request::RollbackConfig(id) => {
+                match config::rollback(id) {
+                    Ok(config) => {
+                        info!("Config rolled back to version {}.", id);
+                        let event = Event::ConfigUpdate(config.clone());
+                        self.event_handler.publish_async(event).await;
+                        let res = Response::Config(config.redacted());
+                        self.tx.unbounded_send(res).ok();
+                    }
+                    Err(err) => {
+                        self.tx.unbounded_send(Response::Error(err)).ok();
+                    }
+                }
+                let res = Response::ConfigHistory(config::history());
+                self.tx.unbounded_send(res).ok();
             }
             Request::GetVersion => {
                 let version = env!("CARGO_PKG_VERSION").to_string();
                 self.tx.unbounded_send(Response::Version(version)).ok();
             }
+            Request::GetQueue => {
+                self.tx.unbounded_send(Response::Queue(queue::get())).ok();
+            }
+            Request::GetApiKeys => {
+                self.tx.unbounded_send(Response::ApiKeys(auth::list_keys())).ok();
+            }
+            Request::CreateApiKey(new_key) => {
+                let name = new_key.name.clone();
+                let res = match auth::create_key(new_key.clone()) {
+                    Ok(key) => {
+                        info!("API key {} created.", name);
+                        Response::ApiKeyCreated(name, key)
+                    }
+                    Err(err) => Response::Error(err)
+                };
+                self.tx.unbounded_send(res).ok();
+                self.send_api_keys();
+            }
+            Request::DeleteApiKey(name) => {
+                if auth::delete_key(name) {
+                    info!("API key {} deleted.", name);
+                }
+                self.send_api_keys();
+            }
             Request::GetTelemetry => {
                 self.tx
                     .unbounded_send(Response::Telemetry(telemetry::get()))
@@ -80,66 +252,186 @@ impl Connection {
             }
             Request::Test => {
                 info!("Initiating test procedure...");
-                self.event_handler.publish(Event::Test);
+                self.event_handler.publish_async(Event::Test).await;
             },
             Request::Restart => {
-                self.event_handler.publish(Event::Restart);
+                self.event_handler.publish_async(Event::Restart).await;
             },
             Request::Shutdown => {
-                self.event_handler.publish(Event::Shutdown);
+                shutdown::request(&self.event_handler).await;
             },
-            Request::Authenticate(_) => {}
+            Request::ChangePassword { old, new } => {
+                let ip = self.addr.ip();
+                match auth::change_password(old, new) {
+                    Ok(()) => {
+                        auth::audit(ip, "password changed");
+                        self.start_session();
+                        self.tx.unbounded_send(Response::PasswordChanged).ok();
+                    }
+                    Err(err) => {
+                        auth::audit(ip, "password change failed");
+                        self.tx.unbounded_send(Response::Error(err)).ok();
+                    }
+                }
+            }
+            Request::Authenticate(_) | Request::ResumeSession(_) |
+            Request::Logout => {}
         }
     }
 }
 
-async fn handle_connection(connections: PeerMap, pass: Option<String>, event_handler: EventHandler, stream: TcpStream) {
-    let addr = stream.peer_addr().unwrap();
-
-    let ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
-
+async fn serve<S>(connections: PeerMap, event_handler: EventHandler, addr: SocketAddr, ws_stream: WebSocketStream<S>)
+    where S: AsyncRead + AsyncWrite + Unpin
+{
     let (tx, rx) = futures::channel::mpsc::unbounded();
     let (sink, stream) = ws_stream.split();
 
     let mut connection = Connection {
         tx: tx.clone(),
-        password: pass.to_owned(),
-        auth: false,
+        addr: addr,
+        connections: connections.clone(),
+        identity: None,
+        session: None,
         event_handler: event_handler
     };
 
-    connections.lock().unwrap().insert(addr, tx);
+    connections.lock().unwrap().insert(addr, Peer { tx, identity: None });
 
-    let ws_reader = stream.try_for_each(|msg| {
-        msg.to_text()
-            .ok()
-            .and_then(|str| serde_json::from_str(&str).ok())
-            .map(|req| connection.handle(&req))
-            .or_else(|| {
-                warn!(
-                    "Received unreadable websocket request."
-                );
-                None
-            });
+    let ws_reader = async {
+        let mut stream = stream;
+        while let Some(Ok(msg)) = stream.next().await {
+            let req = msg.to_text()
+                .ok()
+                .and_then(|str| serde_json::from_str(str).ok());
 
-        futures::future::ok(())
-    });
+            match req {
+                Some(req) => connection.handle(&req).await,
+                None => warn!("Received unreadable websocket request.")
+            }
+        }
+    };
 
     let ws_writer = rx.map(|msg| {
         let data = serde_json::to_string(&msg).unwrap();
         Ok(Message::text(data))
     }).forward(sink);
 
-    futures::future::select(ws_reader, ws_writer).await;
+    tokio::select! {
+        _ = ws_reader => {}
+        _ = ws_writer => {}
+    }
     connections.lock().unwrap().remove(&addr);
 }
 
-pub fn start(runtime: &Runtime, pass: Option<String>, event_handler: EventHandler) {
-    let (tx, rx) = event::channel();
+async fn handle_connection<S>(connections: PeerMap, event_handler: EventHandler, addr: SocketAddr, stream: S)
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws_stream) => serve(connections, event_handler, addr, ws_stream).await,
+        Err(err) => warn!("Websocket handshake with {} failed: {}", addr, err)
+    }
+}
 
-    event_handler.publish(Event::RegisterWebsocket(tx));
+async fn listen(addr: SocketAddr, tls: Option<TlsAcceptor>, websocket: Websocket) {
+    let socket = match TcpListener::bind(&addr).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Failed to start websocket server on {}: {}", addr, err);
+            return;
+        }
+    };
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8055));
+    while let Ok ((stream, addr)) = socket.accept().await {
+        let connections = websocket.connections.clone();
+        let event_handler = websocket.event_handler.clone();
+
+        match tls {
+            Some(ref tls) => {
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    match tls.accept(stream).await {
+                        Ok(stream) => {
+                            handle_connection(connections, event_handler,
+                                              addr, stream).await
+                        }
+                        Err(err) => {
+                            warn!("TLS handshake with {} failed: {}", addr, err)
+                        }
+                    }
+                });
+            }
+            None => {
+                tokio::spawn(
+                    handle_connection(connections, event_handler, addr,
+                                      stream)
+                );
+            }
+        }
+    }
+
+    info!("Shutting down websocket server!");
+}
+
+/// Handle to the websocket server. Connections are either accepted on the
+/// websocket ports or upgraded from the HTTP server.
+#[derive(Clone)]
+pub struct Websocket {
+    connections: PeerMap,
+    event_handler: EventHandler
+}
+
+impl Websocket {
+    /// Answer a websocket upgrade request and serve the connection once the
+    /// upgrade is done.
+    pub fn upgrade(&self, req: hyper::Request<Body>, addr: SocketAddr) -> hyper::Response<Body> {
+        let is_upgrade = req.headers()
+            .get(UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.eq_ignore_ascii_case("websocket"))
+            .unwrap_or(false);
+
+        let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+            Some(key) if is_upgrade => derive_accept_key(key.as_bytes()),
+            _ => {
+                return hyper::Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Expected websocket upgrade"))
+                    .unwrap();
+            }
+        };
+
+        let connections = self.connections.clone();
+        let event_handler = self.event_handler.clone();
+
+        tokio::spawn(async move {
+            match hyper::upgrade::on(req).await {
+                Ok(upgraded) => {
+                    let ws_stream = WebSocketStream::from_raw_socket(
+                        upgraded, Role::Server, None
+                    ).await;
+                    serve(connections, event_handler, addr, ws_stream).await;
+                }
+                Err(err) => {
+                    warn!("Websocket upgrade for {} failed: {}", addr, err)
+                }
+            }
+        });
+
+        hyper::Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(UPGRADE, "websocket")
+            .header(CONNECTION, "Upgrade")
+            .header(SEC_WEBSOCKET_ACCEPT, key)
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+pub fn start(runtime: &Runtime, config: &FrontendConfig, tls: Option<TlsAcceptor>, event_handler: EventHandler) -> Websocket {
+    let rx = event_handler.subscribe(&[
+        Topic::Telemetry, Topic::Timeslot, Topic::Message, Topic::MessageStatus,
+        Topic::Log
+    ]);
 
     let connections: PeerMap = Arc::new(Mutex::new(HashMap::new()));
     let connections_rx = connections.clone();
@@ -148,7 +440,15 @@ pub fn start(runtime: &Runtime, pass: Option<String>, event_handler: EventHandle
         let mut rx = rx;
 
         while let Some(event) = rx.next().await {
-            for (_, connection) in connections_rx.lock().unwrap().iter() {
+            for (_, peer) in connections_rx.lock().unwrap().iter() {
+                let allowed = match peer.identity {
+                    Some(ref identity) => identity.allows(Permission::Read),
+                    None => false
+                };
+                if !allowed {
+                    continue;
+                }
+
                 let response = match event.clone() {
                     Event::TelemetryUpdate(telemetry) => {
                         Some(Response::Telemetry(telemetry))
@@ -159,6 +459,9 @@ pub fn start(runtime: &Runtime, pass: Option<String>, event_handler: EventHandle
                     Event::MessageReceived(msg) => {
                         Some(Response::Message(msg))
                     }
+                    Event::MessageStatus(report) => {
+                        Some(Response::MessageStatus(report))
+                    }
                     Event::Timeslot(timeslot) => {
                         Some(Response::Timeslot(timeslot))
                     }
@@ -169,22 +472,25 @@ pub fn start(runtime: &Runtime, pass: Option<String>, event_handler: EventHandle
                 };
 
                 if let Some(response) = response {
-                    connection.unbounded_send(response).ok();
+                    peer.tx.unbounded_send(response).ok();
                 }
             }
         }
     });
 
-    runtime.spawn(async move {
-        let mut socket = TcpListener::bind(&addr).await.unwrap();
+    let websocket = Websocket {
+        connections,
+        event_handler
+    };
 
-        while let Ok ((stream, _)) = socket.accept().await {
-            tokio::spawn(
-                handle_connection(connections.clone(), pass.to_owned(),
-                                  event_handler.clone(), stream)
-            );
+    for addr in &config.websocket {
+        match addr.parse() {
+            Ok(addr) => {
+                runtime.spawn(listen(addr, tls.clone(), websocket.clone()));
+            }
+            Err(_) => error!("Invalid websocket listen address {}", addr)
         }
+    }
 
-        info!("Shutting down websocket server!");
-    });
+    websocket
 }
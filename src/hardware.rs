@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use async_std::prelude::*;
+use async_std::stream::interval;
+use raspi::Model;
+use tokio::runtime::Runtime;
+
+use crate::config;
+use crate::telemetry::{Hardware, Throttled};
+
+const THERMAL_ZONE: &'static str = "sys/class/thermal/thermal_zone0/temp";
+const THROTTLED: &'static str =
+    "sys/devices/platform/soc/soc:firmware/get_throttled";
+const DEVICE_TREE_MODEL: &'static str = "proc/device-tree/model";
+
+fn read(root: &Path, path: &str) -> Option<String> {
+    fs::read_to_string(root.join(path)).ok()
+}
+
+fn platform(root: &Path) -> String {
+    let model = read(root, "proc/cpuinfo")
+        .map(|cpuinfo| Model::from_cpuinfo(&cpuinfo))
+        .unwrap_or(Model::Unknown);
+
+    if let Model::Unknown = model {
+        // Other boards only identify themselves in the device tree.
+        read(root, DEVICE_TREE_MODEL)
+            .map(|model| model.trim_end_matches('\0').trim().to_owned())
+            .filter(|model| !model.is_empty())
+            .unwrap_or_else(|| model.to_string())
+    }
+    else {
+        model.to_string()
+    }
+}
+
+// Temperature in degrees Celsius, the kernel reports millidegrees.
+fn cpu_temperature(root: &Path) -> Option<f32> {
+    let millis: f32 = read(root, THERMAL_ZONE)?.trim().parse().ok()?;
+    Some(millis / 1000.0)
+}
+
+fn load(root: &Path) -> Option<[f32; 3]> {
+    let loadavg = read(root, "proc/loadavg")?;
+    let mut values = loadavg.split_whitespace().map(|value| value.parse());
+
+    Some([
+        values.next()?.ok()?,
+        values.next()?.ok()?,
+        values.next()?.ok()?
+    ])
+}
+
+// Total and available memory in kB
+fn memory(root: &Path) -> (Option<u64>, Option<u64>) {
+    let meminfo = read(root, "proc/meminfo").unwrap_or_default();
+
+    let value = |key: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(key))
+            .and_then(|line| line[key.len()..].split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+    };
+
+    (value("MemTotal:"), value("MemAvailable:"))
+}
+
+fn uptime(root: &Path) -> Option<u64> {
+    let uptime = read(root, "proc/uptime")?;
+    let seconds: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(seconds as u64)
+}
+
+// Throttling flags of the Raspberry Pi firmware
+fn throttled(root: &Path) -> Option<Throttled> {
+    let value = read(root, THROTTLED)?;
+    let value = value.trim().trim_start_matches("0x");
+    let flags = u32::from_str_radix(value, 16).ok()?;
+    let bit = |n: u32| flags & (1 << n) != 0;
+
+    Some(Throttled {
+        under_voltage: bit(0),
+        frequency_capped: bit(1),
+        throttled: bit(2),
+        soft_temperature_limit: bit(3),
+        under_voltage_occurred: bit(16),
+        frequency_capped_occurred: bit(17),
+        throttled_occurred: bit(18),
+        soft_temperature_limit_occurred: bit(19)
+    })
+}
+
+/// Read the hardware state from the proc and sys file systems below root.
+pub fn read_hardware(root: &Path) -> Hardware {
+    let (memory_total, memory_available) = memory(root);
+
+    Hardware {
+        platform: platform(root),
+        transmitter: String::new(),
+        cpu_temperature: cpu_temperature(root),
+        load: load(root),
+        memory_total,
+        memory_available,
+        uptime: uptime(root),
+        throttled: throttled(root)
+    }
+}
+
+fn update() {
+    let mut hardware = read_hardware(Path::new("/"));
+    hardware.transmitter = config::get().transmitter.to_string();
+
+    if let Some(ref throttled) = hardware.throttled {
+        if throttled.under_voltage {
+            warn!("Under-voltage detected, check the power supply!");
+        }
+    }
+
+    telemetry_update!(hardware: |current: &mut Hardware| {
+        *current = hardware;
+    });
+}
+
+pub fn start(runtime: &Runtime) {
+    update();
+
+    runtime.spawn(async move {
+        let mut interval = interval(Duration::from_secs(30));
+
+        while let Some(_) = interval.next().await {
+            update();
+        }
+    });
+}
+
+#[test]
+pub fn test_read_hardware() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/hardware");
+    let hardware = read_hardware(&root);
+
+    assert_eq!(hardware.platform, "Raspberry Pi 3 Model B");
+    assert_eq!(hardware.cpu_temperature, Some(48.312));
+    assert_eq!(hardware.load, Some([0.52, 0.31, 0.12]));
+    assert_eq!(hardware.memory_total, Some(948304));
+    assert_eq!(hardware.memory_available, Some(706220));
+    assert_eq!(hardware.uptime, Some(86523));
+
+    let throttled = hardware.throttled.unwrap();
+    assert!(!throttled.under_voltage);
+    assert!(throttled.under_voltage_occurred);
+    assert!(throttled.throttled_occurred);
+
+    let empty = read_hardware(&root.join("missing"));
+    assert_eq!(empty.platform, "Unknown Device");
+    assert_eq!(empty.cpu_temperature, None);
+    assert!(empty.throttled.is_none());
+}
@@ -22,6 +22,7 @@ extern crate reqwest;
 
 #[macro_use]
 mod telemetry;
+mod cli;
 mod config;
 mod logging;
 mod connection;
@@ -34,10 +35,13 @@ mod scheduler;
 mod timeslots;
 mod queue;
 mod event;
+mod hardware;
+mod metrics;
+mod ntp;
+mod shutdown;
+mod source;
 
-use std::fs::File;
-use std::io::Read;
-
+use clap::Parser;
 use tokio::runtime::Runtime;
 use async_std::prelude::*;
 
@@ -51,39 +55,81 @@ fn print_version() {
 }
 
 fn main() {
+    let args = cli::Args::parse();
+
+    if let Err(err) = cli::apply_paths(&args) {
+        eprintln!("Failed to change to the data directory: {}", err);
+        std::process::exit(1);
+    }
+
+    if args.print_default_config {
+        cli::print_default_config();
+        return;
+    }
+
+    if args.print_config {
+        std::process::exit(cli::print_config());
+    }
+
+    if args.check_config {
+        std::process::exit(cli::check_config());
+    }
+
     print_version();
 
-    let pass = File::open("password")
-        .and_then(|mut f| {
-            let mut s = String::new();
-            f.read_to_string(&mut s)?;
-            Ok(s)
-        })
-        .map(|s| s.trim().to_owned())
-        .map_err(|_| eprintln!("Failed to load password file."))
-        .ok();
+    if let Some(command) = args.command {
+        logging::init(event::EventHandler::new());
+        std::process::exit(cli::run(command));
+    }
+
+    frontend::password::init();
 
     let mut runtime = Runtime::new().unwrap();
 
-    let config = config::get();
-    let event_handler = event::start(&runtime);
-
+    let event_handler = event::EventHandler::new();
     logging::init(event_handler.clone());
-    scheduler::start(config.clone(), event_handler.clone());
+
+    if let Err(err) = config::init() {
+        error!("Failed to read the config file {}: {}",
+               config::path().display(), err);
+        error!("Fix the config file, e.g. with the help of --check-config.");
+        std::process::exit(1);
+    }
+
+    let config = config::get();
+    let scheduler = scheduler::start(config.clone(), event_handler.clone());
+    shutdown::start(&runtime, event_handler.clone());
     telemetry::start(&runtime, event_handler.clone());
-    frontend::websocket::start(&runtime, pass, event_handler.clone());
-    frontend::http::start(&runtime, event_handler.clone());
+    ntp::start(&runtime);
+    hardware::start(&runtime);
+    let tls = match frontend::tls::acceptor(&config.tls) {
+        Ok(tls) => tls,
+        Err(err) => {
+            error!("Failed to set up TLS: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let websocket = frontend::websocket::start(
+        &runtime, &config.frontend, tls.clone(), event_handler.clone()
+    );
+    frontend::http::start(
+        &runtime, &config.frontend, tls, websocket, event_handler.clone()
+    );
+    frontend::snpp::start(&runtime, &config, event_handler.clone());
+    source::start(&runtime, &config, event_handler.clone());
     if config.master.standalone_mode {
         info!("Starting up in standalone mode. Connection to server is skipped.")
     } else {
         timeslots::start(&runtime, event_handler.clone());
         connection::start(&runtime, &config, event_handler.clone());
-        core::start(&runtime, &config, event_handler.clone());
+        if config.master.protocol == config::MasterProtocol::Core {
+            core::start(&runtime, &config, event_handler.clone());
+        }
     }
 
     runtime.block_on(async move {
-        let (tx, mut rx) = event::channel();
-        event_handler.publish(event::Event::RegisterMain(tx));
+        let mut rx = event_handler.subscribe(&[event::Topic::Control]);
 
         while let Some(event) = rx.next().await {
             match event {
@@ -95,5 +141,8 @@ fn main() {
         }
     });
 
+    let timeout = config::get().shutdown.timeout;
+    shutdown::wait(scheduler, std::time::Duration::from_secs(timeout));
+
     info!("Terminating... 73!");
 }
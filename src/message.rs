@@ -24,6 +24,41 @@ pub struct Message {
     pub message: ProtocolMessage
 }
 
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "status")]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Queued,
+    Transmitted { slot: usize },
+    Expired,
+    Dropped { reason: String },
+    Failed { reason: String }
+}
+
+/// Delivery status of a single message, reported back to the core and
+/// to the connected frontend clients.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub id: String,
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub status: Status,
+    // Name of the additional transmitter, not set for the main transmitter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transmitter: Option<String>
+}
+
+impl StatusReport {
+    pub fn new(id: &str, status: Status) -> StatusReport {
+        StatusReport {
+            id: id.to_owned(),
+            time: Utc::now(),
+            status,
+            transmitter: None
+        }
+    }
+}
+
 impl Message {
     pub fn is_expired(&self) -> bool {
         match self.expires_on
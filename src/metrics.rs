@@ -0,0 +1,143 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::telemetry::{self, Telemetry};
+
+lazy_static! {
+    static ref COUNTERS: Mutex<Counters> = Mutex::new(Counters::default());
+}
+
+/// Values that are not part of the telemetry.
+#[derive(Default, Debug, Clone)]
+pub struct Counters {
+    pub transmissions: u64,
+    pub onair_seconds: f64,
+    pub pll_lock_failures: u64,
+    pub reconnects: u64,
+    pub events_dropped: u64,
+    // Time budget of the last transmission and how much of it was used, in
+    // codewords.
+    pub budget: usize,
+    pub budget_used: usize
+}
+
+pub fn transmission(duration: Duration, budget: usize, budget_used: usize) {
+    let mut counters = COUNTERS.lock().unwrap();
+    counters.transmissions += 1;
+    counters.onair_seconds += duration.as_secs_f64();
+    counters.budget = budget;
+    counters.budget_used = budget_used;
+}
+
+pub fn pll_lock_failure() {
+    COUNTERS.lock().unwrap().pll_lock_failures += 1;
+}
+
+pub fn reconnect() {
+    COUNTERS.lock().unwrap().reconnects += 1;
+}
+
+pub fn event_dropped() {
+    COUNTERS.lock().unwrap().events_dropped += 1;
+}
+
+struct Writer {
+    output: String
+}
+
+impl Writer {
+    fn metric(&mut self, name: &str, kind: &str, help: &str, value: f64) {
+        self.header(name, kind, help);
+        self.sample(name, "", value);
+    }
+
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.output, "# HELP unipager_{} {}", name, help).ok();
+        writeln!(self.output, "# TYPE unipager_{} {}", name, kind).ok();
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: f64) {
+        writeln!(self.output, "unipager_{}{} {}", name, labels, value).ok();
+    }
+}
+
+fn flag(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+/// Render the metrics in the Prometheus text exposition format.
+pub fn render(telemetry: &Telemetry, counters: &Counters) -> String {
+    let mut w = Writer { output: String::new() };
+
+    w.header("messages_queued", "gauge", "Messages waiting in the queue.");
+    for (index, count) in telemetry.messages.queued.iter().enumerate() {
+        let labels = format!("{{priority=\"{}\"}}", index + 1);
+        w.sample("messages_queued", &labels, *count as f64);
+    }
+
+    w.header("messages_sent_total", "counter", "Messages sent.");
+    for (index, count) in telemetry.messages.sent.iter().enumerate() {
+        let labels = format!("{{priority=\"{}\"}}", index + 1);
+        w.sample("messages_sent_total", &labels, *count as f64);
+    }
+
+    w.metric("onair", "gauge", "Whether the transmitter is on air.",
+             flag(telemetry.onair));
+    w.metric("onair_seconds_total", "counter", "Time spent on air.",
+             counters.onair_seconds);
+    w.metric("transmissions_total", "counter", "Transmissions started.",
+             counters.transmissions as f64);
+    w.metric("pll_lock_failures_total", "counter",
+             "Failed attempts to lock the PLL.",
+             counters.pll_lock_failures as f64);
+    w.metric("master_connected", "gauge",
+             "Whether the connection to the master is established.",
+             flag(telemetry.node.connected));
+    w.metric("master_reconnects_total", "counter",
+             "Reconnects to the master.", counters.reconnects as f64);
+    w.metric("events_dropped_total", "counter",
+             "Events dropped because a subscriber did not keep up.",
+             counters.events_dropped as f64);
+
+    w.metric("timeslots_allowed", "gauge", "Number of allowed timeslots.",
+             telemetry.timeslots.count_allowed() as f64);
+    w.metric("timeslot_budget", "gauge",
+             "Time budget of the last transmission in codewords.",
+             counters.budget as f64);
+    w.metric("timeslot_budget_used", "gauge",
+             "Codewords used of the budget in the last transmission.",
+             counters.budget_used as f64);
+
+    w.metric("ntp_synced", "gauge", "Whether the system clock is synchronized.",
+             flag(telemetry.ntp.synced));
+    w.metric("ntp_offset_seconds", "gauge", "Offset of the system clock.",
+             telemetry.ntp.offset as f64 / 1000.0);
+
+    w.output
+}
+
+pub fn get() -> String {
+    let counters = COUNTERS.lock().unwrap().clone();
+    render(&telemetry::get(), &counters)
+}
+
+#[test]
+pub fn test_render() {
+    let mut telemetry = Telemetry::default();
+    telemetry.messages.queued[2] = 4;
+    telemetry.onair = true;
+
+    let counters = Counters {
+        transmissions: 3,
+        onair_seconds: 1.5,
+        ..Counters::default()
+    };
+
+    let output = render(&telemetry, &counters);
+    assert!(output.contains("# TYPE unipager_messages_queued gauge\n"));
+    assert!(output.contains("unipager_messages_queued{priority=\"3\"} 4\n"));
+    assert!(output.contains("unipager_onair 1\n"));
+    assert!(output.contains("unipager_onair_seconds_total 1.5\n"));
+    assert!(output.contains("unipager_transmissions_total 3\n"));
+}
@@ -0,0 +1,165 @@
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use async_std::prelude::*;
+use async_std::stream::interval;
+use tokio::runtime::Runtime;
+
+use crate::config::NtpConfig;
+use crate::telemetry;
+
+// Return value of adjtimex if the clock is not synchronized
+const TIME_ERROR: i32 = 5;
+// Status flag indicating that the offset is given in nanoseconds
+const STA_NANO: i32 = 0x2000;
+
+const CONFIG_FILES: [&str; 5] = [
+    "/etc/chrony/chrony.conf",
+    "/etc/chrony.conf",
+    "/etc/ntp.conf",
+    "/etc/ntpsec/ntp.conf",
+    "/etc/systemd/timesyncd.conf"
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockState {
+    pub synced: bool,
+    // Offset to the reference time in milliseconds
+    pub offset: isize
+}
+
+/// Query the synchronisation state of the system clock from the kernel.
+#[cfg(target_os = "linux")]
+pub fn query() -> io::Result<ClockState> {
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+
+    // With modes set to zero the clock is not modified.
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let offset = if timex.status & STA_NANO != 0 {
+        timex.offset / 1_000_000
+    } else {
+        timex.offset / 1_000
+    };
+
+    Ok(ClockState {
+        synced: state != TIME_ERROR,
+        offset: offset as isize
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn query() -> io::Result<ClockState> {
+    Err(io::Error::new(io::ErrorKind::Other, "Not supported on this platform"))
+}
+
+// Extract the configured time servers from a chrony, ntpd or timesyncd
+// configuration file.
+fn parse_servers(content: &str) -> Vec<String> {
+    let mut servers = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        let mut parts = line.split_whitespace();
+
+        match parts.next() {
+            Some("server") | Some("pool") | Some("peer") => {
+                servers.extend(parts.next().map(str::to_owned));
+            }
+            Some(key) if key.starts_with("NTP=") => {
+                let value = line["NTP=".len()..].split_whitespace();
+                servers.extend(value.map(str::to_owned));
+            }
+            _ => {}
+        }
+    }
+
+    servers
+}
+
+fn servers() -> Vec<String> {
+    CONFIG_FILES
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|content| parse_servers(&content))
+        .collect()
+}
+
+/// Check whether the clock is good enough to transmit in the timeslots.
+/// Depending on the config a bad clock only results in a warning.
+pub fn transmission_allowed(config: &NtpConfig) -> bool {
+    let state = match query() {
+        Ok(state) => state,
+        Err(_) => return true
+    };
+
+    let max_offset = config.max_offset as isize;
+
+    if state.synced && state.offset.abs() <= max_offset {
+        return true;
+    }
+
+    if !state.synced {
+        warn!("System clock is not synchronized, timeslots may be violated!");
+    }
+    else {
+        warn!(
+            "System clock offset of {} ms exceeds the limit of {} ms, \
+             timeslots may be violated!",
+            state.offset,
+            max_offset
+        );
+    }
+
+    !config.enforce
+}
+
+fn update() {
+    match query() {
+        Ok(state) => {
+            let servers = servers();
+            telemetry_update!(ntp: |ntp: &mut telemetry::Ntp| {
+                *ntp = telemetry::Ntp {
+                    synced: state.synced,
+                    offset: state.offset,
+                    servers: servers
+                };
+            });
+        }
+        Err(err) => {
+            warn!("Failed to query the clock state: {}", err);
+        }
+    }
+}
+
+pub fn start(runtime: &Runtime) {
+    update();
+
+    runtime.spawn(async move {
+        let mut interval = interval(Duration::from_secs(60));
+
+        while let Some(_) = interval.next().await {
+            update();
+        }
+    });
+}
+
+#[test]
+pub fn test_parse_servers() {
+    let content = "# comment\n\
+                   pool 2.debian.pool.ntp.org iburst\n\
+                   server ntp.example.org\n\
+                   driftfile /var/lib/chrony/chrony.drift\n\
+                   NTP=time1.example.org time2.example.org\n";
+
+    assert_eq!(parse_servers(content), vec![
+        "2.debian.pool.ntp.org",
+        "ntp.example.org",
+        "time1.example.org",
+        "time2.example.org"
+    ]);
+}
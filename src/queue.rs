@@ -1,5 +1,12 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
+use serde_json;
+
+use crate::config;
 use crate::message::Message;
 use crate::telemetry;
 
@@ -8,41 +15,128 @@ use crate::telemetry;
 // for the network UniPager is connecting to.
 pub const NUM_PRIORITIES: usize = 5;
 
+lazy_static! {
+    // Snapshot of the queued messages of each transmitter for inspection by
+    // the frontends.
+    static ref QUEUED: RwLock<BTreeMap<Option<String>, Vec<Message>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+// Returns the queued messages, highest priority first. Messages queued for
+// several transmitters are only listed once.
+pub fn get() -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    for message in QUEUED.read().unwrap().values().flatten() {
+        if !messages.iter().any(|queued| queued.id == message.id) {
+            messages.push(message.clone());
+        }
+    }
+    messages
+}
+
+// Messages that were still queued at shutdown
+fn queue_file(transmitter: Option<&str>) -> PathBuf {
+    match transmitter {
+        None => PathBuf::from("queue.json"),
+        Some(name) => PathBuf::from(format!("queue.{}.json", name))
+    }
+}
+
+/// Store messages that could not be sent before shutting down.
+pub fn save(transmitter: Option<&str>, messages: &[Message]) -> io::Result<()> {
+    save_to(&queue_file(transmitter), messages)
+}
+
+/// Take the messages stored at the last shutdown.
+pub fn load(transmitter: Option<&str>) -> Vec<Message> {
+    load_from(&queue_file(transmitter))
+}
+
+fn save_to(path: &Path, messages: &[Message]) -> io::Result<()> {
+    // The process may be killed while the queue is stored.
+    let data = serde_json::to_vec(messages)?;
+    config::write_atomic(path, &data)
+}
+
+fn load_from(path: &Path) -> Vec<Message> {
+    let messages = match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            error!("Failed to parse stored queue: {}", err);
+            Vec::new()
+        }),
+        Err(_) => return Vec::new()
+    };
+
+    fs::remove_file(path).ok();
+    messages
+}
+
 // The queue. This is a priority queue. It contains subqueues for each priority.
 // From the outside it looks just like a normal queue.
 pub struct Queue {
+    // Name of the transmitter the queue belongs to, None for the main one
+    transmitter: Option<String>,
     queues: Vec<VecDeque<Message>>,
     sent: [usize; NUM_PRIORITIES]
 }
 
 impl Queue {
-    pub fn new() -> Queue {
+    pub fn new(transmitter: Option<String>) -> Queue {
         Queue {
+            transmitter,
             queues: (0..NUM_PRIORITIES).map(|_| VecDeque::new()).collect(),
             sent: [0; NUM_PRIORITIES]
         }
     }
 
-    pub fn enqueue(&mut self, message: Message) {
-        self.queues
-            .get_mut(message.priority - 1)
-            .map(|queue| { queue.push_back(message); })
-            .or_else(|| {
+    pub fn enqueue(&mut self, message: Message) -> Result<(), Message> {
+        match message.priority.checked_sub(1)
+            .and_then(|index| self.queues.get_mut(index))
+        {
+            Some(queue) => {
+                queue.push_back(message);
+                self.update_snapshot();
+                Ok(())
+            }
+            None => {
                 error!("Tried to enqueue message for out of range priority.");
-                None
-            });
+                Err(message)
+            }
+        }
     }
 
     pub fn dequeue(&mut self) -> Option<Message> {
         for (priority, queue) in self.queues.iter_mut().enumerate().rev() {
             if !queue.is_empty() {
                 self.sent[priority] += 1;
-                return queue.pop_front();
+                let message = queue.pop_front();
+                self.update_snapshot();
+                return message;
             }
         }
         None
     }
 
+    // Removes all messages from the queue, highest priority first.
+    pub fn drain(&mut self) -> Vec<Message> {
+        let messages = self.queues
+            .iter_mut()
+            .rev()
+            .flat_map(|queue| queue.drain(..))
+            .collect();
+        self.update_snapshot();
+        messages
+    }
+
+    fn update_snapshot(&self) {
+        let messages = self.queues
+            .iter()
+            .rev()
+            .flat_map(|queue| queue.iter().cloned())
+            .collect();
+        QUEUED.write().unwrap().insert(self.transmitter.clone(), messages);
+    }
+
     pub fn len(&self) -> usize {
         self.queues.iter().map(&VecDeque::len).sum()
     }
@@ -59,3 +153,34 @@ impl Queue {
         messages.sent = self.sent;
     }
 }
+
+#[test]
+pub fn test_queue_persistence() {
+    use crate::message::ProtocolMessage;
+    use crate::pocsag;
+
+    let path = std::env::temp_dir().join("unipager-test-queue.json");
+    let message = Message {
+        id: "1".to_owned(),
+        priority: 3,
+        origin: "test".to_owned(),
+        expires_on: None,
+        message: ProtocolMessage::Pocsag(pocsag::Message {
+            ric: 1234,
+            mtype: pocsag::MessageType::AlphaNum,
+            speed: 1200,
+            func: 3,
+            data: "Hello".to_owned()
+        })
+    };
+
+    save_to(&path, &[message]).unwrap();
+
+    let messages = load_from(&path);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id, "1");
+    assert_eq!(messages[0].priority, 3);
+
+    // The file is removed after loading.
+    assert!(load_from(&path).is_empty());
+}
@@ -1,104 +1,423 @@
-use std::sync::mpsc::{self, Receiver, TryRecvError};
-use std::thread;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::config::Config;
-use crate::event::{Event, EventHandler};
-use crate::message::{Message, MessageProvider};
+use chrono::{DateTime, Utc};
+
+use crate::config::{Config, ConfigChanges};
+use crate::event::{Event, EventHandler, Topic};
+use crate::message::{Message, MessageProvider, ProtocolMessage, Status, StatusReport};
+use crate::metrics;
+use crate::ntp;
 use crate::pocsag::TestGenerator;
-use crate::queue::Queue;
-use crate::timeslots::TimeSlots;
-use crate::transmitter::{self, Transmitter};
+use crate::queue::{self, Queue};
+use crate::shutdown;
+use crate::telemetry;
+use crate::timeslots::{TimeSlot, TimeSlots};
+use crate::transmitter::{self, Health, Transmitter};
+
+// Delay before the transmitter initialization is retried, doubled after every
+// failure up to the maximum.
+const INIT_RETRY_MIN: Duration = Duration::from_secs(5);
+const INIT_RETRY_MAX: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    // Number of transmitters that are currently on air
+    static ref ON_AIR: AtomicUsize = AtomicUsize::new(0);
+}
 
 struct Scheduler {
+    // Name of the transmitter in `transmitters`, None for the main transmitter
+    name: Option<String>,
+    // Effective config of the transmitter
     config: Config,
     rx: Receiver<Event>,
+    event_handler: EventHandler,
     slots: TimeSlots,
+    // Timeslots assigned by the master
+    master_slots: TimeSlots,
     queue: Queue,
+    // IDs of the messages in the current transmission
+    transmitted: Vec<String>,
     budget: usize,
+    // Codewords of the budget used by the current transmission
+    used: usize,
+    // Config changes the transmitter has not been reconfigured for yet
+    changes: Option<ConfigChanges>,
+    retry_delay: Duration,
     test: bool,
     stop: bool,
     restart: bool,
+    // The transmitter was removed from the config
+    removed: bool,
+}
+
+/// Start a scheduler for each transmitter. The returned thread finishes when
+/// all schedulers have stopped.
+pub fn start(config: Config, event_handler: EventHandler) -> JoinHandle<()> {
+    let rx = event_handler.subscribe_sync(&[
+        Topic::Config, Topic::Timeslots, Topic::Control
+    ]);
+
+    thread::spawn(move || {
+        let mut config = config;
+        // Latest timeslots assigned by the master for schedulers started later
+        let mut master_slots = TimeSlots::new();
+        let mut schedulers: Vec<(Option<String>, JoinHandle<()>)> = Vec::new();
+        // Schedulers of removed transmitters that are still finishing
+        let mut removed: Vec<(Option<String>, JoinHandle<()>)> = Vec::new();
+
+        loop {
+            let names = config.transmitters.iter().map(|t| Some(t.name.clone()));
+            let names: Vec<Option<String>> = Some(None).into_iter().chain(names).collect();
+
+            let (kept, gone) = schedulers.into_iter()
+                .partition(|(name, _)| names.contains(name));
+            schedulers = kept;
+            removed.extend(gone);
+
+            for name in names {
+                if schedulers.iter().any(|(running, _)| *running == name) {
+                    continue;
+                }
+                // A transmitter that is added again must not share its queue
+                // file with the old scheduler.
+                if let Some(i) = removed.iter().position(|(old, _)| *old == name) {
+                    removed.remove(i).1.join().ok();
+                }
+                let scheduler = start_transmitter(
+                    name.clone(), &config, master_slots, &event_handler
+                );
+                schedulers.push((name, scheduler));
+            }
+
+            match rx.recv() {
+                Ok(Event::ConfigUpdate(new_config)) => config = new_config,
+                Ok(Event::TimeslotsUpdate(slots)) => master_slots = slots,
+                Ok(Event::Shutdown) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+
+        for (_, scheduler) in schedulers.into_iter().chain(removed) {
+            scheduler.join().ok();
+        }
+    })
 }
 
-pub fn start(config: Config, event_handler: EventHandler) {
-    let (tx, rx) = mpsc::channel();
-    event_handler.publish(Event::RegisterScheduler(tx));
+fn start_transmitter(
+    name: Option<String>,
+    config: &Config,
+    master_slots: TimeSlots,
+    event_handler: &EventHandler
+) -> JoinHandle<()> {
+    let rx = event_handler.subscribe_sync(&[
+        Topic::Config, Topic::Message, Topic::Timeslots, Topic::Control
+    ]);
+    let config = config.for_transmitter(name.as_deref()).unwrap();
+    let event_handler = event_handler.clone();
 
     thread::spawn(move || {
-        let mut scheduler = Scheduler::new(config, rx);
+        let mut scheduler =
+            Scheduler::new(name, config, master_slots, rx, event_handler);
         scheduler.start();
-    });
+    })
 }
 
 impl Scheduler {
-    pub fn new(config: Config, rx: Receiver<Event>) -> Scheduler {
-        Scheduler {
+    pub fn new(
+        name: Option<String>,
+        config: Config,
+        master_slots: TimeSlots,
+        rx: Receiver<Event>,
+        event_handler: EventHandler
+    ) -> Scheduler {
+        let mut scheduler = Scheduler {
+            queue: Queue::new(name.clone()),
+            name,
             config,
             rx,
+            event_handler,
             slots: TimeSlots::new(),
-            queue: Queue::new(),
+            master_slots,
+            transmitted: Vec::new(),
             budget: 0,
+            used: 0,
+            changes: None,
+            retry_delay: INIT_RETRY_MIN,
             test: false,
             stop: false,
             restart: true,
-        }
+            removed: false,
+        };
+        scheduler.update_slots();
+        scheduler
+    }
+
+    fn is_main(&self) -> bool {
+        self.name.is_none()
     }
 
     pub fn start(&mut self) {
+        if let Some(ref name) = self.name {
+            info!("Starting the scheduler of transmitter {}.", name);
+        }
+
+        for message in queue::load(self.name.as_deref()) {
+            info!("Restoring message {} from the last shutdown.", message.id);
+            self.queue.enqueue(message).ok();
+        }
+
         loop {
-            let transmitter = transmitter::from_config(&self.config);
-            if self.test {
-                self.test(transmitter);
-                self.test = false;
-            } else {
-                self.run(transmitter);
+            match transmitter::from_config(&self.config) {
+                Ok(mut transmitter) => {
+                    self.retry_delay = INIT_RETRY_MIN;
+                    self.set_health(transmitter.health(), None);
+                    if self.is_main() {
+                        telemetry_update!(transmitter: |t: &mut telemetry::TransmitterState| {
+                            t.capabilities = transmitter.capabilities();
+                        });
+                    }
+
+                    if self.test {
+                        self.test(&mut *transmitter);
+                        self.test = false;
+                    } else {
+                        self.run(&mut *transmitter);
+                    }
+                    transmitter.shutdown();
+                }
+                Err(err) => {
+                    let delay = self.retry_delay;
+                    error!("Failed to initialize the transmitter: {}. Retrying in \
+                            {} seconds.", err, delay.as_secs());
+
+                    let retry = Utc::now() + chrono::Duration::from_std(delay).unwrap();
+                    self.set_health(Health::Failed { reason: err.to_string() }, Some(retry));
+
+                    // Queued messages are kept until the transmitter works.
+                    self.retry_delay = (delay * 2).min(INIT_RETRY_MAX);
+                    self.wait_for_retry(delay);
+                }
             }
 
-            if !self.restart {
+            if !self.restart || shutdown::aborted() {
                 info!("Shutting down the scheduler...");
+                let messages = self.queue.drain();
+                // Nobody would restore the queue of a removed transmitter.
+                let dropped = if self.removed {
+                    Some("transmitter removed")
+                } else if let Err(err) = queue::save(self.name.as_deref(), &messages) {
+                    error!("Failed to store the queue: {}", err);
+                    Some("shutdown")
+                } else {
+                    None
+                };
+                if let Some(reason) = dropped {
+                    for message in messages {
+                        self.report(&message.id, Status::Dropped {
+                            reason: reason.to_owned()
+                        });
+                    }
+                }
                 return;
             } else {
                 info!("Restarting the scheduler...");
                 self.stop = false;
+                self.changes = None;
             }
         }
     }
 
-    pub fn run(&mut self, mut transmitter: Box<dyn Transmitter>) {
+    pub fn run(&mut self, transmitter: &mut dyn Transmitter) {
         info!("Scheduler started.");
 
         loop {
+            if let Some(changes) = self.changes.take() {
+                if !transmitter.reconfigure(&self.config, &changes) {
+                    info!("Transmitter config changed, reinitializing...");
+                    return;
+                }
+                info!("Transmitter reconfigured.");
+            }
+
             while self.queue.is_empty() {
                 info!("Queue Empty, waiting for events.");
                 self.process_next_event();
                 if self.stop { return; }
+                if self.changes.is_some() { break; }
             }
 
+            if self.changes.is_some() { continue; }
+
             if self.config.master.standalone_mode {
                 debug!("Sending message in standalone mode without synchronization of time slots.");
             } else {
                 info!("Queue not empty, waiting for next Timeslot. {} message(s) waiting.", self.queue.len());
                 self.wait_for_next_timeslot();
                 if self.stop { return; }
+                if self.changes.is_some() { continue; }
                 info!("Available time budget: {}", self.budget);
             }
 
-            let message = self.queue.dequeue().unwrap();
-            telemetry_update!(messages: |m| {
-                self.queue.telemetry_update(m);
+            if !ntp::transmission_allowed(&self.config.ntp) {
+                if let Some(event) = self.recv_event_timeout(Duration::from_secs(10)) {
+                    self.process_event(event);
+                }
+                continue;
+            }
+
+            let message = match self.dequeue() {
+                Some(message) => message,
+                None => continue
+            };
+
+            self.update_telemetry();
+
+            let time = Utc::now();
+            let slot = TimeSlot::current();
+            self.transmitted.push(message.id.clone());
+
+            self.used = 0;
+            let start = Instant::now();
+
+            set_onair(true);
+            let result = transmitter.send(
+                &mut message.generator(self).take_while(|_| !shutdown::aborted())
+            );
+            set_onair(false);
+
+            metrics::transmission(start.elapsed(), self.budget, self.used);
+
+            let status = match result {
+                Err(err) => {
+                    error!("Transmission failed: {}", err);
+                    Status::Failed { reason: err.to_string() }
+                }
+                Ok(()) if shutdown::aborted() => {
+                    Status::Failed { reason: "transmission aborted".to_owned() }
+                }
+                Ok(()) => Status::Transmitted { slot: slot.index() }
+            };
+
+            let transmitted: Vec<String> = self.transmitted.drain(..).collect();
+            for id in transmitted {
+                self.event_handler.publish(Event::MessageStatus(StatusReport {
+                    id,
+                    time,
+                    status: status.clone(),
+                    transmitter: self.name.clone()
+                }));
+            }
+
+            let health = transmitter.health();
+            self.set_health(health.clone(), None);
+            if let Health::Failed { reason } = health {
+                error!("Transmitter failed: {}, reinitializing...", reason);
+                return;
+            }
+
+            if self.stop { return; }
+        }
+    }
+
+    pub fn test(&mut self, transmitter: &mut dyn Transmitter) {
+        let start = Instant::now();
+
+        set_onair(true);
+        let result = transmitter.send(
+            &mut TestGenerator::new(1125).take_while(|_| !shutdown::aborted())
+        );
+        set_onair(false);
+
+        if let Err(err) = result {
+            error!("Test transmission failed: {}", err);
+        }
+
+        metrics::transmission(start.elapsed(), 0, 0);
+    }
+
+    // Wait for the next initialization attempt. The config or a restart
+    // cause an immediate retry.
+    fn wait_for_retry(&mut self, delay: Duration) {
+        let deadline = Instant::now() + delay;
+
+        while !self.stop && self.changes.is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+
+            match self.rx.recv_timeout(deadline - now) {
+                Ok(event) => self.process_event(event),
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.stop = true;
+                    self.restart = false;
+                }
+            }
+        }
+
+        // The changed config is used for the next attempt.
+        if self.changes.take().is_some() {
+            self.retry_delay = INIT_RETRY_MIN;
+        }
+    }
+
+    fn set_health(&self, health: Health, next_retry: Option<DateTime<Utc>>) {
+        if self.is_main() {
+            telemetry_update!(transmitter: |t: &mut telemetry::TransmitterState| {
+                t.health = health;
+                t.next_retry = next_retry;
             });
+        }
+    }
 
-            telemetry!(onair: true);
-            transmitter.send(&mut *message.generator(self));
-            telemetry!(onair: false);
+    // Returns the next message that has not expired yet.
+    fn dequeue(&mut self) -> Option<Message> {
+        while let Some(message) = self.queue.dequeue() {
+            if message.is_expired() {
+                info!("Message {} expired before transmission.", message.id);
+                self.report(&message.id, Status::Expired);
+            } else {
+                return Some(message);
+            }
         }
+        None
+    }
+
+    fn report(&self, id: &str, status: Status) {
+        let mut report = StatusReport::new(id, status);
+        report.transmitter = self.name.clone();
+        self.event_handler.publish(Event::MessageStatus(report));
+    }
+
+    // Use the fixed timeslots of the transmitter if it has any.
+    fn update_slots(&mut self) {
+        let fixed = &self.config.routing.timeslots;
+        self.slots = if fixed.is_empty() {
+            self.master_slots
+        } else {
+            fixed.parse().unwrap_or_default()
+        };
     }
 
-    pub fn test(&mut self, mut transmitter: Box<dyn Transmitter>) {
-        telemetry!(onair: true);
-        transmitter.send(&mut TestGenerator::new(1125));
-        telemetry!(onair: false);
+    // Whether any transmitter accepts the RIC.
+    fn routable(&self, ric: u32) -> bool {
+        self.config.routing.accepts(ric) ||
+            self.config.transmitters.iter().any(|transmitter| {
+                transmitter.routing.accepts(ric)
+            })
+    }
+
+    // Queue telemetry is only reported for the main transmitter.
+    fn update_telemetry(&self) {
+        if self.is_main() {
+            telemetry_update!(messages: |m| {
+                self.queue.telemetry_update(m);
+            });
+        }
     }
 
     fn wait_for_next_timeslot(&mut self) {
@@ -122,7 +441,7 @@ impl Scheduler {
                 None => return,
             }
 
-            if self.stop { return; }
+            if self.stop || self.changes.is_some() { return; }
         }
     }
 
@@ -136,19 +455,69 @@ impl Scheduler {
         match event
         {
             Event::MessageReceived(msg) => {
-                self.queue.enqueue(msg);
-                telemetry_update!(messages: |m| {
-                    self.queue.telemetry_update(m);
-                });
+                if !self.config.routing.accepts(ric(&msg)) {
+                    // The main transmitter reports messages nobody sends.
+                    if self.is_main() && !self.routable(ric(&msg)) {
+                        self.report(&msg.id, Status::Dropped {
+                            reason: "no transmitter for this RIC".to_owned()
+                        });
+                    }
+                    return;
+                }
+
+                let id = msg.id.clone();
+                match self.queue.enqueue(msg) {
+                    Ok(()) => self.report(&id, Status::Queued),
+                    Err(_) => self.report(&id, Status::Dropped {
+                        reason: "priority out of range".to_owned()
+                    })
+                }
+                self.update_telemetry();
             }
             Event::TimeslotsUpdate(slots) => {
-                self.slots = slots;
-                telemetry!(timeslots: slots);
+                self.master_slots = slots;
+                self.update_slots();
+                if self.is_main() {
+                    telemetry!(timeslots: slots);
+                }
             }
             Event::ConfigUpdate(config) => {
+                let config = match config.for_transmitter(self.name.as_deref()) {
+                    Some(config) => config,
+                    None => {
+                        info!("Transmitter {} was removed.",
+                              self.name.as_deref().unwrap_or_default());
+                        self.stop = true;
+                        self.restart = false;
+                        self.removed = true;
+                        return;
+                    }
+                };
+
+                let changes = ConfigChanges::new(&self.config, &config);
+                if changes.is_empty() {
+                    return;
+                }
                 self.config = config;
-                self.stop = true;
-                self.restart = true;
+
+                if changes.contains("routing.timeslots") {
+                    self.update_slots();
+                }
+
+                // The GPIO pins are only requested on initialization.
+                if changes.contains("transmitter") || changes.contains("gpio") {
+                    self.stop = true;
+                    self.restart = true;
+                }
+                else if transmitter::CONFIG_SECTIONS
+                    .iter()
+                    .any(|section| changes.contains(section))
+                {
+                    match self.changes {
+                        Some(ref mut pending) => pending.extend(changes),
+                        None => self.changes = Some(changes)
+                    }
+                }
             }
             Event::Test => {
                 self.test = true;
@@ -176,6 +545,23 @@ impl Scheduler {
     }
 }
 
+// RIC of the message, used for the routing.
+fn ric(message: &Message) -> u32 {
+    match message.message {
+        ProtocolMessage::Pocsag(ref pocsag) => pocsag.ric
+    }
+}
+
+// The telemetry shows whether any transmitter is on air.
+fn set_onair(onair: bool) {
+    let count = if onair {
+        ON_AIR.fetch_add(1, Ordering::SeqCst) + 1
+    } else {
+        ON_AIR.fetch_sub(1, Ordering::SeqCst) - 1
+    };
+    telemetry!(onair: count > 0);
+}
+
 impl MessageProvider for Scheduler {
     fn next(&mut self, count: usize) -> Option<Message> {
         debug!(
@@ -183,6 +569,8 @@ impl MessageProvider for Scheduler {
             self.budget as i32 - count as i32
         );
 
+        self.used = count;
+
         if count + 30 > self.budget {
             return None;
         }
@@ -203,11 +591,19 @@ impl MessageProvider for Scheduler {
             };
         }
 
-        let message = self.queue.dequeue();
+        // Finish the transmission after the current message when shutting
+        // down, the remaining messages are kept for the next start.
+        if self.stop && !self.restart {
+            return None;
+        }
 
-        telemetry_update!(messages: |m| {
-            self.queue.telemetry_update(m);
-        });
+        let message = self.dequeue();
+
+        self.update_telemetry();
+
+        if let Some(ref message) = message {
+            self.transmitted.push(message.id.clone());
+        }
 
         message
     }
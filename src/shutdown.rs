@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{self, Config};
+use crate::event::{Event, EventHandler};
+use crate::transmitter::ptt;
+
+// Time the transmitter gets to power down after a transmission was aborted.
+const ABORT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    static ref ABORT: AtomicBool = AtomicBool::new(false);
+}
+
+/// Whether the current transmission should be stopped immediately.
+pub fn aborted() -> bool {
+    ABORT.load(Ordering::SeqCst)
+}
+
+pub fn abort() {
+    ABORT.store(true, Ordering::SeqCst);
+}
+
+/// Shut down, either after the current transmission or by aborting it,
+/// depending on the drain mode.
+pub async fn request(event_handler: &EventHandler) {
+    if !config::get().shutdown.drain {
+        abort();
+    }
+    event_handler.publish_async(Event::Shutdown).await;
+}
+
+async fn reload(event_handler: &EventHandler) {
+    info!("Reloading the config file...");
+
+    let config = match Config::read(&config::path()) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Failed to reload the config file: {}", err);
+            return;
+        }
+    };
+
+    if let Err(errors) = config::replace(config.clone()) {
+        for error in errors {
+            error!("Invalid config: {}", error);
+        }
+        warn!("Keeping the current config.");
+        return;
+    }
+
+    event_handler.publish_async(Event::ConfigUpdate(config)).await;
+}
+
+pub fn start(runtime: &Runtime, event_handler: EventHandler) {
+    runtime.spawn(async move {
+        let mut terminate = signal(SignalKind::terminate())
+            .expect("Failed to register SIGTERM handler");
+        let mut interrupt = signal(SignalKind::interrupt())
+            .expect("Failed to register SIGINT handler");
+        let mut hangup = signal(SignalKind::hangup())
+            .expect("Failed to register SIGHUP handler");
+
+        let mut shutting_down = false;
+
+        loop {
+            let name = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+                _ = hangup.recv() => {
+                    reload(&event_handler).await;
+                    continue;
+                }
+            };
+
+            if shutting_down {
+                warn!("Received {} again, aborting the transmission.", name);
+                abort();
+            }
+            else {
+                info!("Received {}, shutting down...", name);
+                shutting_down = true;
+                request(&event_handler).await;
+            }
+        }
+    });
+}
+
+/// Wait for the scheduler to finish. If it takes longer than the configured
+/// timeout the transmission is aborted, if it still does not stop the PTTs
+/// are forced off.
+pub fn wait(scheduler: JoinHandle<()>, timeout: Duration) {
+    let start = Instant::now();
+
+    while !scheduler.is_finished() {
+        if start.elapsed() > timeout && !aborted() {
+            warn!(
+                "Scheduler did not stop within {} seconds, aborting the \
+                 transmission.",
+                timeout.as_secs()
+            );
+            abort();
+        }
+
+        if start.elapsed() > timeout + ABORT_GRACE_PERIOD {
+            error!("Scheduler is not responding, forcing the PTT off.");
+            if !ptt::release_all() {
+                error!("The PTT may still be active!");
+            }
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    scheduler.join().ok();
+}
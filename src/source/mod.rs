@@ -0,0 +1,40 @@
+pub mod mqtt;
+pub mod tap;
+
+pub use self::mqtt::MqttSource;
+pub use self::tap::TapSource;
+
+use tokio::runtime::Runtime;
+
+use crate::config::Config;
+use crate::event::EventHandler;
+
+/// A source of messages to be transmitted.
+///
+/// Every message received by a source is published as
+/// `Event::MessageReceived`, just like calls from the core connection.
+pub trait MessageSource {
+    fn name(&self) -> &'static str;
+    fn start(self: Box<Self>, runtime: &Runtime, event_handler: EventHandler);
+}
+
+pub fn from_config(config: &Config) -> Vec<Box<dyn MessageSource>> {
+    let mut sources: Vec<Box<dyn MessageSource>> = Vec::new();
+
+    if config.mqtt.enabled {
+        sources.push(Box::new(MqttSource::new(&config.mqtt)));
+    }
+
+    if config.tap.enabled {
+        sources.push(Box::new(TapSource::new(&config.tap)));
+    }
+
+    sources
+}
+
+pub fn start(runtime: &Runtime, config: &Config, event_handler: EventHandler) {
+    for source in from_config(config) {
+        info!("Starting {} message source.", source.name());
+        source.start(runtime, event_handler.clone());
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use futures_timer::Delay;
+use rumqttc::{self, AsyncClient, Event as MqttEvent, MqttOptions, Packet,
+              Publish, QoS};
+use serde_json;
+use tokio::runtime::Runtime;
+
+use crate::config::MqttConfig;
+use crate::event::{Event, EventHandler};
+use crate::message::Message;
+use crate::source::MessageSource;
+
+pub struct MqttSource {
+    config: MqttConfig
+}
+
+impl MqttSource {
+    pub fn new(config: &MqttConfig) -> MqttSource {
+        MqttSource { config: config.clone() }
+    }
+
+    async fn run(self, event_handler: EventHandler) {
+        let qos = rumqttc::qos(self.config.qos).unwrap_or_else(|_| {
+            error!("Invalid MQTT QoS {}. Using QoS 1.", self.config.qos);
+            QoS::AtLeastOnce
+        });
+
+        let mut options = MqttOptions::new(
+            &*self.config.client_id,
+            &*self.config.server,
+            self.config.port
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+
+        if !self.config.username.is_empty() {
+            options.set_credentials(
+                &*self.config.username,
+                &*self.config.password
+            );
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+        info!(
+            "Connecting to MQTT broker {}:{}...",
+            self.config.server, self.config.port
+        );
+
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    // Subscriptions are renewed after every reconnect.
+                    info!("Connected to MQTT broker. Subscribing to {:?}.",
+                          self.config.topics);
+
+                    for topic in &self.config.topics {
+                        if client.try_subscribe(&**topic, qos).is_err() {
+                            error!("Failed to subscribe to MQTT topic {}", topic);
+                        }
+                    }
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    handle_publish(&publish, &event_handler).await;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    error!(
+                        "MQTT connection failed: {}. Retrying in {} Seconds...",
+                        err, self.config.reconnect_timeout
+                    );
+                    Delay::new(
+                        Duration::from_secs(self.config.reconnect_timeout)
+                    ).await;
+                }
+            }
+        }
+    }
+}
+
+fn parse(payload: &[u8]) -> serde_json::Result<Message> {
+    serde_json::from_slice(payload)
+}
+
+async fn handle_publish(publish: &Publish, event_handler: &EventHandler) {
+    match parse(&publish.payload) {
+        Ok(msg) => {
+            info!("Message received via MQTT: {:?}", msg);
+            // Wait for the scheduler to keep up with the broker.
+            event_handler.publish_async(Event::MessageReceived(msg)).await;
+        }
+        Err(err) => {
+            warn!(
+                "Could not decode MQTT message on topic {}: {}",
+                publish.topic, err
+            );
+        }
+    }
+}
+
+impl MessageSource for MqttSource {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    fn start(self: Box<Self>, runtime: &Runtime, event_handler: EventHandler) {
+        runtime.spawn(async move { self.run(event_handler).await; });
+    }
+}
+
+#[test]
+pub fn test_mqtt_parse() {
+    use crate::message::ProtocolMessage;
+
+    let payload = br#"{
+        "id": "abc", "priority": 3, "origin": "mqtt",
+        "protocol": "pocsag",
+        "message": { "type": "alphanum", "speed": 1200, "ric": 123,
+                     "func": 3, "data": "Hello" }
+    }"#;
+
+    let msg = parse(payload).unwrap();
+    assert_eq!(msg.id, "abc");
+    assert_eq!(msg.priority, 3);
+    match msg.message {
+        ProtocolMessage::Pocsag(ref msg) => {
+            assert_eq!(msg.ric, 123);
+            assert_eq!(msg.data, "Hello");
+        }
+    }
+
+    assert!(parse(b"Hello").is_err());
+    assert!(parse(br#"{"id": "abc", "protocol": "pocsag"}"#).is_err());
+    assert!(parse(&payload[..payload.len() - 1]).is_err());
+}
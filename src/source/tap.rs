@@ -0,0 +1,438 @@
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use serial::{self, SerialPort};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+use crate::config::TapConfig;
+use crate::event::{Event, EventHandler};
+use crate::message::{Message, ProtocolMessage};
+use crate::pocsag;
+use crate::queue::NUM_PRIORITIES;
+use crate::source::MessageSource;
+
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const ETB: u8 = 0x17;
+const ESC: u8 = 0x1B;
+const RS: u8 = 0x1E;
+const US: u8 = 0x1F;
+const CR: u8 = 0x0D;
+
+// Limits for the data of a client that is not terminated properly
+const MAX_BUFFER: usize = 4096;
+const MAX_FIELDS: usize = 4096;
+// Failed logons before the connection is closed
+const MAX_LOGON_FAILURES: u32 = 3;
+
+static MESSAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Calculate the TAP checksum of a block from STX up to and including the
+// terminating ETX, ETB or US character.
+fn checksum(block: &[u8]) -> [u8; 3] {
+    let sum = block.iter().map(|&byte| (byte & 0x7F) as u32).sum::<u32>();
+    [
+        0x30 + ((sum >> 8) & 0xF) as u8,
+        0x30 + ((sum >> 4) & 0xF) as u8,
+        0x30 + (sum & 0xF) as u8,
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Logon,
+    Transaction,
+    Closed
+}
+
+/// Result of feeding data into a TAP session.
+#[derive(Default)]
+pub struct Output {
+    pub response: Vec<u8>,
+    pub messages: Vec<Message>,
+    pub close: bool
+}
+
+/// Transport independent TAP protocol state machine.
+pub struct Session {
+    config: TapConfig,
+    state: State,
+    buffer: Vec<u8>,
+    // Content of the blocks of the current transaction
+    fields: Vec<u8>,
+    logon_failures: u32
+}
+
+impl Session {
+    pub fn new(config: &TapConfig) -> Session {
+        Session {
+            config: config.clone(),
+            state: State::Logon,
+            buffer: Vec::new(),
+            fields: Vec::new(),
+            logon_failures: 0
+        }
+    }
+
+    pub fn input(&mut self, data: &[u8]) -> Output {
+        let mut output = Output::default();
+        self.buffer.extend_from_slice(data);
+
+        while self.state != State::Closed {
+            let consumed = match self.state {
+                State::Logon => self.logon(&mut output),
+                State::Transaction => self.transaction(&mut output),
+                State::Closed => None
+            };
+
+            match consumed {
+                Some(count) => { self.buffer.drain(..count); }
+                None => break
+            }
+        }
+
+        if self.buffer.len() > MAX_BUFFER && self.state != State::Closed {
+            warn!("TAP client sent too much unterminated data, closing.");
+            output.response.extend_from_slice(&[NAK, CR]);
+            output.response.extend_from_slice(&[ESC, EOT, CR]);
+            self.buffer.clear();
+            self.state = State::Closed;
+        }
+
+        output.close = self.state == State::Closed;
+        output
+    }
+
+    fn logon(&mut self, output: &mut Output) -> Option<usize> {
+        let end = self.buffer.iter().position(|&byte| byte == CR)?;
+        let line = &self.buffer[..end];
+
+        if line.is_empty() {
+            output.response.extend_from_slice(b"ID=");
+        }
+        else if line.starts_with(&[ESC, b'P', b'G', b'1']) {
+            let password = String::from_utf8_lossy(&line[4..]);
+
+            if self.config.password.is_empty() ||
+                password.trim() == self.config.password {
+                output.response.extend_from_slice(b"110 1.8\r");
+                output.response.extend_from_slice(&[ACK, CR]);
+                output.response.extend_from_slice(&[ESC, b'[', b'p', CR]);
+                self.state = State::Transaction;
+            }
+            else {
+                warn!("TAP logon with invalid password rejected.");
+                output.response.extend_from_slice(&[NAK, CR]);
+
+                self.logon_failures += 1;
+                if self.logon_failures >= MAX_LOGON_FAILURES {
+                    output.response.extend_from_slice(&[ESC, EOT, CR]);
+                    self.state = State::Closed;
+                }
+            }
+        }
+        else if line == [EOT] {
+            output.response.extend_from_slice(&[ESC, EOT, CR]);
+            self.state = State::Closed;
+        }
+
+        Some(end + 1)
+    }
+
+    fn transaction(&mut self, output: &mut Output) -> Option<usize> {
+        // Skip everything in front of the start of the next block
+        let start = self.buffer
+            .iter()
+            .position(|&byte| byte == STX || byte == EOT)?;
+
+        if self.buffer[start] == EOT {
+            let end = self.buffer[start..].iter().position(|&b| b == CR)?;
+            output.response.extend_from_slice(b"115 Closing\r");
+            output.response.extend_from_slice(&[ESC, EOT, CR]);
+            self.state = State::Closed;
+            return Some(start + end + 1);
+        }
+
+        let terminator = self.buffer[start..]
+            .iter()
+            .position(|&byte| byte == ETX || byte == ETB || byte == US)?;
+        let terminator = start + terminator;
+
+        // The terminator is followed by three checksum characters and CR.
+        if self.buffer.len() < terminator + 5 {
+            return None;
+        }
+
+        let block = &self.buffer[start..=terminator];
+        let received = &self.buffer[terminator + 1..terminator + 4];
+
+        if checksum(block) != received {
+            warn!("TAP block with invalid checksum received.");
+            output.response.extend_from_slice(&[NAK, CR]);
+            return Some(terminator + 5);
+        }
+
+        if self.fields.len() + block.len() > MAX_FIELDS {
+            warn!("TAP transaction too long, rejected.");
+            self.fields.clear();
+            output.response.extend_from_slice(&[NAK, CR]);
+            return Some(terminator + 5);
+        }
+
+        self.fields.extend_from_slice(&block[1..block.len() - 1]);
+
+        if self.buffer[terminator] != ETX {
+            // The transaction is continued in the next block.
+            output.response.extend_from_slice(&[ACK, CR]);
+            return Some(terminator + 5);
+        }
+
+        let fields = String::from_utf8_lossy(&self.fields).into_owned();
+        self.fields.clear();
+
+        let mut fields = fields.split('\r');
+        let pager_id = fields.next().unwrap_or("").trim().to_owned();
+        let data = fields.next().unwrap_or("").to_owned();
+
+        match self.message(&pager_id, data) {
+            Some(message) => {
+                info!("Page for pager {} received via TAP.", pager_id);
+                output.messages.push(message);
+                output.response.extend_from_slice(b"211 Page Sent\r");
+                output.response.extend_from_slice(&[ACK, CR]);
+            }
+            None => {
+                warn!("TAP page for unknown pager {} rejected.", pager_id);
+                output.response.extend_from_slice(b"511 Invalid Pager ID\r");
+                output.response.extend_from_slice(&[RS, CR]);
+            }
+        }
+
+        Some(terminator + 5)
+    }
+
+    fn message(&self, pager_id: &str, data: String) -> Option<Message> {
+        let pager = self.config.pagers
+            .iter()
+            .find(|pager| pager.id == pager_id);
+
+        let message = match pager {
+            Some(pager) => pocsag::Message {
+                mtype: pager.mtype,
+                ric: pager.ric,
+                func: pager.func,
+                data,
+                ..pocsag::Message::default()
+            },
+            None if self.config.numeric_ids => pocsag::Message {
+                ric: pager_id.parse().ok()?,
+                func: self.config.func,
+                data,
+                ..pocsag::Message::default()
+            },
+            None => return None
+        };
+
+        let counter = MESSAGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        Some(Message {
+            id: format!("tap-{}-{}", Utc::now().timestamp(), counter),
+            priority: self.config.priority.max(1).min(NUM_PRIORITIES),
+            origin: "tap".to_owned(),
+            expires_on: None,
+            message: ProtocolMessage::Pocsag(message)
+        })
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    config: TapConfig,
+    event_handler: EventHandler
+) -> io::Result<()> {
+    let mut session = Session::new(&config);
+    let mut buffer = [0u8; 512];
+
+    loop {
+        let count = stream.read(&mut buffer).await?;
+        if count == 0 {
+            return Ok(());
+        }
+
+        let output = session.input(&buffer[..count]);
+        stream.write_all(&output.response).await?;
+        for message in output.messages {
+            event_handler.publish_async(Event::MessageReceived(message)).await;
+        }
+
+        if output.close {
+            return Ok(());
+        }
+    }
+}
+
+fn run_serial(config: &TapConfig, event_handler: &EventHandler) -> io::Result<()> {
+    let mut port = serial::open(&*config.serial_port)?;
+
+    port.configure(&serial::PortSettings {
+        baud_rate: serial::BaudRate::from_speed(config.baudrate),
+        char_size: serial::CharSize::Bits7,
+        parity: serial::Parity::ParityEven,
+        stop_bits: serial::StopBits::Stop1,
+        flow_control: serial::FlowControl::FlowNone
+    })?;
+    port.set_timeout(Duration::from_secs(1))?;
+
+    info!("TAP server listening on {}.", config.serial_port);
+
+    let mut session = Session::new(config);
+    let mut buffer = [0u8; 512];
+
+    loop {
+        let count = match port.read(&mut buffer) {
+            Ok(count) => count,
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => return Err(err)
+        };
+
+        let output = session.input(&buffer[..count]);
+        port.write_all(&output.response)?;
+        for message in output.messages {
+            event_handler.publish(Event::MessageReceived(message));
+        }
+
+        // The serial line stays open for the next caller.
+        if output.close {
+            session = Session::new(config);
+        }
+    }
+}
+
+pub struct TapSource {
+    config: TapConfig
+}
+
+impl TapSource {
+    pub fn new(config: &TapConfig) -> TapSource {
+        TapSource { config: config.clone() }
+    }
+}
+
+impl MessageSource for TapSource {
+    fn name(&self) -> &'static str {
+        "TAP"
+    }
+
+    fn start(self: Box<Self>, runtime: &Runtime, event_handler: EventHandler) {
+        if !self.config.serial_port.is_empty() {
+            let config = self.config.clone();
+            let event_handler = event_handler.clone();
+
+            thread::spawn(move || loop {
+                if let Err(err) = run_serial(&config, &event_handler) {
+                    error!(
+                        "TAP serial port {} failed: {}. Retrying in 10 Seconds...",
+                        config.serial_port, err
+                    );
+                }
+                thread::sleep(Duration::from_secs(10));
+            });
+        }
+
+        if self.config.listen.is_empty() {
+            return;
+        }
+
+        let addr: SocketAddr = match self.config.listen.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                error!("Invalid TAP listen address {}", self.config.listen);
+                return;
+            }
+        };
+
+        let config = self.config;
+
+        runtime.spawn(async move {
+            let listener = match TcpListener::bind(&addr).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Failed to start TAP server on {}: {}", addr, err);
+                    return;
+                }
+            };
+
+            info!("TAP server listening on {}.", addr);
+
+            while let Ok((stream, peer)) = listener.accept().await {
+                info!("TAP connection from {}.", peer);
+                let config = config.clone();
+                let event_handler = event_handler.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) =
+                        handle_connection(stream, config, event_handler).await {
+                        warn!("TAP connection from {} failed: {}", peer, err);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[test]
+pub fn test_tap_checksum() {
+    let block = b"\x02123\rABC\r\x03";
+    assert_eq!(&checksum(block), b"17;");
+}
+
+#[test]
+pub fn test_tap_session() {
+    let mut session = Session::new(&TapConfig::default());
+
+    assert_eq!(session.input(b"\r").response, b"ID=");
+    assert!(session.input(b"\x1bPG1\r").response.ends_with(b"\x1b[p\r"));
+
+    let output = session.input(b"\x02123\rABC\r\x0317;\r");
+    assert!(output.response.ends_with(&[ACK, CR]));
+    assert_eq!(output.messages.len(), 1);
+
+    match output.messages[0].message {
+        ProtocolMessage::Pocsag(ref msg) => {
+            assert_eq!(msg.ric, 123);
+            assert_eq!(msg.data, "ABC");
+        }
+    }
+
+    let output = session.input(b"\x02123\rABC\r\x0317:\r");
+    assert!(output.response.ends_with(&[NAK, CR]));
+    assert!(output.messages.is_empty());
+
+    let output = session.input(b"\x04\r");
+    assert!(output.close);
+}
+
+#[test]
+pub fn test_tap_limits() {
+    let mut config = TapConfig::default();
+    config.password = String::from("secret");
+
+    let mut session = Session::new(&config);
+    assert!(!session.input(b"\x1bPG1wrong\r\x1bPG1wrong\r").close);
+    assert!(session.input(b"\x1bPG1wrong\r").close);
+
+    let mut session = Session::new(&config);
+    session.input(b"\x1bPG1secret\r");
+    let output = session.input(&vec![b'A'; MAX_BUFFER + 1]);
+    assert!(output.close);
+    assert!(session.buffer.is_empty());
+}
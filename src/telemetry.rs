@@ -9,6 +9,7 @@ use tokio::runtime::Runtime;
 use crate::event::{Event, EventHandler};
 use crate::queue::NUM_PRIORITIES;
 use crate::timeslots::TimeSlots;
+use crate::transmitter::{Capabilities, Health};
 
 lazy_static! {
     pub static ref TELEMETRY: RwLock<Telemetry> = RwLock::new(Telemetry::default());
@@ -57,9 +58,39 @@ pub struct Config {
     pub software: TransmitterSoftware
 }
 
-#[derive(Default, Debug, Serialize, Clone)]
+#[derive(Default, Debug, Serialize, Clone, PartialEq)]
+pub struct Throttled {
+    pub under_voltage: bool,
+    pub frequency_capped: bool,
+    pub throttled: bool,
+    pub soft_temperature_limit: bool,
+    pub under_voltage_occurred: bool,
+    pub frequency_capped_occurred: bool,
+    pub throttled_occurred: bool,
+    pub soft_temperature_limit_occurred: bool
+}
+
+#[derive(Default, Debug, Serialize, Clone, PartialEq)]
 pub struct Hardware {
-    pub platform: String
+    pub platform: String,
+    pub transmitter: String,
+    // Degrees Celsius
+    pub cpu_temperature: Option<f32>,
+    pub load: Option<[f32; 3]>,
+    // kB
+    pub memory_total: Option<u64>,
+    pub memory_available: Option<u64>,
+    // Seconds
+    pub uptime: Option<u64>,
+    pub throttled: Option<Throttled>
+}
+
+#[derive(Default, Debug, Serialize, Clone, PartialEq)]
+pub struct TransmitterState {
+    pub health: Health,
+    pub capabilities: Capabilities,
+    // Next initialization attempt after a failure
+    pub next_retry: Option<DateTime<Utc>>
 }
 
 #[derive(Default, Debug, Serialize, Clone)]
@@ -70,7 +101,8 @@ pub struct Telemetry {
     pub ntp: Ntp,
     pub messages: Messages,
     pub config: Config,
-    pub hardware: Hardware
+    pub hardware: Hardware,
+    pub transmitter: TransmitterState
 }
 
 pub fn get() -> Telemetry {
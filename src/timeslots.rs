@@ -106,6 +106,10 @@ impl TimeSlots {
         TimeSlots(timeslots)
     }
 
+    pub fn count_allowed(&self) -> usize {
+        self.0.iter().filter(|&&allowed| allowed).count()
+    }
+
     pub fn is_allowed(&self, slot: TimeSlot) -> bool {
         self.0.get(slot.index()).cloned().unwrap_or(false)
     }
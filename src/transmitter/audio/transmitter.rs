@@ -3,9 +3,8 @@ use std::process::{Command, Stdio};
 use std::thread::sleep;
 use std::time::Duration;
 
-use crate::config::Config;
-use crate::transmitter::Ptt;
-use crate::transmitter::Transmitter;
+use crate::config::{Config, ConfigChanges};
+use crate::transmitter::{Capabilities, Error, Health, Ptt, Result, Transmitter};
 
 const SAMPLE_RATE: usize = 48000;
 
@@ -16,10 +15,11 @@ pub struct AudioTransmitter {
     level: u8,
     tx_delay: usize,
     samples_per_bit: usize,
+    health: Health,
 }
 
 impl AudioTransmitter {
-    pub fn new(config: &Config) -> AudioTransmitter {
+    pub fn new(config: &Config) -> Result<AudioTransmitter> {
         info!("Initializing audio transmitter with baudrate '{}'...", config.audio.baudrate);
 
         let device = match &*config.audio.device {
@@ -29,31 +29,59 @@ impl AudioTransmitter {
 
         let mut transmitter = AudioTransmitter {
             device,
-            ptt: Ptt::from_config(&config.ptt),
-            inverted: config.audio.inverted,
-            level: config.audio.level,
-            tx_delay: config.audio.tx_delay,
+            ptt: Ptt::from_config(&config.ptt, &config.gpio)?,
+            inverted: false,
+            level: 0,
+            tx_delay: 0,
             samples_per_bit: SAMPLE_RATE / config.audio.baudrate,
+            health: Health::Ok,
         };
 
-        if transmitter.level > 127 {
-            transmitter.level = 127;
-        }
+        transmitter.apply(config);
+
+        Ok(transmitter)
+    }
 
-        transmitter.ptt.set(false);
+    // Play the samples with aplay.
+    fn play(device: &str, buffer: &[u8]) -> Result<()> {
+        trace!("Spawning `aplay` child process to start audio transmission.");
+        let mut child = Command::new("aplay")
+            .stdin(Stdio::piped())
+            .args(&["-t", "raw", "-N", "-f", "U8", "-c", "1"])
+            .args(&["-r", &*format!("{}", SAMPLE_RATE)])
+            .args(&["-D", device])
+            .spawn()
+            .map_err(|err| Error::Audio(format!("Failed to start aplay: {}", err)))?;
+
+        let written = child
+            .stdin
+            .take()
+            .ok_or_else(|| Error::Audio("aplay has no stdin".to_owned()))
+            .and_then(|mut stdin| {
+                stdin.write_all(buffer).map_err(|err| {
+                    Error::Audio(format!("Failed to write to aplay stdin: {}", err))
+                })
+            });
+
+        let status = child.wait()?;
+        written?;
+
+        if !status.success() {
+            return Err(Error::Audio(format!("aplay exited with {}", status)));
+        }
+        Ok(())
+    }
 
-        transmitter
+    // Settings that only affect the generated samples
+    fn apply(&mut self, config: &Config) {
+        self.inverted = config.audio.inverted;
+        self.level = config.audio.level.min(127);
+        self.tx_delay = config.audio.tx_delay;
     }
 }
 
 impl Transmitter for AudioTransmitter {
-    fn send(&mut self, gen: &mut dyn Iterator<Item=u32>) {
-        trace!("Activating PTT to start transmission.");
-        self.ptt.set(true);
-
-        trace!("Waiting for {}ms before audio transmission starts.", self.tx_delay);
-        sleep(Duration::from_millis(self.tx_delay as u64));
-
+    fn send(&mut self, gen: &mut dyn Iterator<Item=u32>) -> Result<()> {
         let mut buffer: Vec<u8> = Vec::with_capacity(SAMPLE_RATE);
         let low_level = 127 - self.level;
         let high_level = 128 + self.level;
@@ -76,33 +104,52 @@ impl Transmitter for AudioTransmitter {
             }
         }
 
-        trace!("Spawning `aplay` child process to start audio transmission.");
-        let child = Command::new("aplay")
-            .stdin(Stdio::piped())
-            .args(&["-t", "raw", "-N", "-f", "U8", "-c", "1"])
-            .args(&["-r", &*format!("{}", SAMPLE_RATE)])
-            .args(&["-D", &*self.device])
-            .spawn();
-
-        if let Ok(mut child) = child {
-            let result = child
-                .stdin
-                .as_mut()
-                .and_then(|stdin| {
-                    stdin.write_all(buffer.as_slice()).ok()
-                });
-
-            if result.is_none() {
-                error!("Failed to write to aplay stdin")
+        trace!("Activating PTT to start transmission.");
+        let keyed = match self.ptt.key() {
+            Ok(keyed) => keyed,
+            Err(err) => {
+                self.health = Health::Failed { reason: err.to_string() };
+                return Err(err);
             }
+        };
 
-            child.wait().ok();
-        } else {
-            error!("Failed to start aplay");
-        }
+        trace!("Waiting for {}ms before audio transmission starts.", self.tx_delay);
+        sleep(Duration::from_millis(self.tx_delay as u64));
+
+        let result = AudioTransmitter::play(&self.device, &buffer);
 
         trace!("Deactivating PTT to end transmission.");
-        self.ptt.set(false);
+        let released = keyed.release();
+
+        self.health = match (&result, &released) {
+            (_, &Err(ref err)) => Health::Failed { reason: err.to_string() },
+            (&Err(ref err), _) => Health::Degraded { reason: err.to_string() },
+            _ => Health::Ok
+        };
+
+        result.and(released)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            baud_rates: vec![512, 1200, 2400],
+            max_power: Some(127)
+        }
+    }
+
+    fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    fn reconfigure(&mut self, config: &Config, changes: &ConfigChanges) -> bool {
+        if changes.contains("ptt") || changes.contains("audio.device") ||
+            changes.contains("audio.baudrate")
+        {
+            return false;
+        }
+
+        self.apply(config);
+        true
     }
 }
 
@@ -1,10 +1,13 @@
 use raspi::{Direction, Gpio, Model, Pin};
 use serial::{self, SerialPort};
 use std::{thread, time};
-use std::io::{Write};
+use std::io::{self, Write};
 
-use crate::config::Config;
-use crate::transmitter::Transmitter;
+use crate::config::{Config, ConfigChanges};
+use crate::transmitter::{self, Error, Health, Result, Transmitter};
+
+// Time the C9000 may take to empty its buffer
+const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(10);
 
 pub struct C9000Transmitter {
     reset_pin: Box<dyn Pin>,
@@ -12,11 +15,12 @@ pub struct C9000Transmitter {
     send_pin: Box<dyn Pin>,
     status_led_pin: Box<dyn Pin>,
     connected_led_pin: Box<dyn Pin>,
-    serial: Box<dyn serial::SerialPort>
+    serial: Box<dyn serial::SerialPort>,
+    health: Health
 }
 
 impl C9000Transmitter {
-    pub fn new(config: &Config) -> C9000Transmitter {
+    pub fn new(config: &Config) -> Result<C9000Transmitter> {
         info!("Initializing C9000 transmitter...");
 
         if config.c9000.dummy_enabled {
@@ -46,10 +50,20 @@ impl C9000Transmitter {
         let model = Model::get();
         info!("Detected {}", model);
 
-        let mut serial = serial::open(model.serial_port()).expect(
-            "Unable to open serial port"
-        );
+        let serial = serial::open(model.serial_port()).map_err(|err| {
+            Error::Init(format!("Unable to open serial port {}: {}",
+                                model.serial_port(), err))
+        })?;
+
+        let gpio = transmitter::gpio(&config.gpio)?;
 
+        C9000Transmitter::with_hardware(&gpio, Box::new(serial))
+    }
+
+    pub fn with_hardware(
+        gpio: &Gpio,
+        mut serial: Box<dyn SerialPort>
+    ) -> Result<C9000Transmitter> {
         serial
             .configure(&serial::PortSettings {
                 baud_rate: serial::BaudRate::Baud38400,
@@ -58,40 +72,38 @@ impl C9000Transmitter {
                 stop_bits: serial::StopBits::Stop1,
                 flow_control: serial::FlowControl::FlowNone
             })
-            .expect("Unable to configure serial port");
-
-        let gpio = Gpio::new().expect("Failed to map GPIO");
+            .map_err(|err| {
+                Error::Init(format!("Unable to configure serial port: {}", err))
+            })?;
 
         let transmitter = C9000Transmitter {
-            reset_pin: gpio.pin(0, Direction::Output),
-            ptt_pin: gpio.pin(2, Direction::Output),
-            send_pin: gpio.pin(3, Direction::Input),
-            status_led_pin: gpio.pin(10, Direction::Output),
-            connected_led_pin: gpio.pin(11, Direction::Output),
-            serial: Box::new(serial)
+            reset_pin: gpio.pin(0, Direction::Output)?,
+            ptt_pin: gpio.pin(2, Direction::Output)?,
+            send_pin: gpio.pin(3, Direction::Input)?,
+            status_led_pin: gpio.pin(10, Direction::Output)?,
+            connected_led_pin: gpio.pin(11, Direction::Output)?,
+            serial,
+            health: Health::Ok
         };
 
         transmitter.reset_pin.set_high();
         transmitter.status_led_pin.set_high();
         transmitter.connected_led_pin.set_high();
 
-        transmitter
+        Ok(transmitter)
     }
-}
-
-impl Transmitter for C9000Transmitter {
-    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) {
-        self.ptt_pin.set_high();
 
+    fn write(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
         for (i, word) in gen.enumerate() {
             if i % 10 == 0 {
-                if (*self.serial).flush().is_err() {
-                    error!("Unable to flush serial port");
-                }
+                (*self.serial).flush()?;
 
                 thread::sleep(time::Duration::from_millis(10));
-                while !self.send_pin.read() {
-                    thread::sleep(time::Duration::from_millis(1));
+                if !self.send_pin.wait_for(true, SEND_TIMEOUT) {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "C9000 is not ready to receive"
+                    )));
                 }
             }
 
@@ -102,17 +114,73 @@ impl Transmitter for C9000Transmitter {
                 (word & 0x000000ff) as u8,
             ];
 
-            if (*self.serial).write_all(&bytes).is_err() {
-                error!("Unable to write data to the serial port");
-                self.ptt_pin.set_low();
-                return;
-            }
+            (*self.serial).write_all(&bytes)?;
         }
 
-        if (*self.serial).flush().is_err() {
-            error!("Unable to flush serial port");
-        }
+        (*self.serial).flush()?;
+        Ok(())
+    }
+}
+
+impl Transmitter for C9000Transmitter {
+    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
+        self.ptt_pin.set_high();
+        let result = self.write(gen);
+        self.ptt_pin.set_low();
 
+        self.health = match result {
+            Ok(()) => Health::Ok,
+            Err(ref err) => Health::Failed { reason: err.to_string() }
+        };
+        result
+    }
+
+    fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    fn shutdown(&mut self) {
         self.ptt_pin.set_low();
     }
+
+    // The dummy PA output level is only set on initialization.
+    fn reconfigure(&mut self, _: &Config, changes: &ConfigChanges) -> bool {
+        !changes.contains("c9000")
+    }
+}
+
+impl Drop for C9000Transmitter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[test]
+pub fn test_c9000_flow_control() {
+    use raspi::MockGpio;
+    use crate::transmitter::mock::MockSerial;
+
+    let gpio = MockGpio::new();
+    let port = MockSerial::new();
+
+    // The C9000 is busy twice before it accepts the first block.
+    gpio.script(3, &[false, false]);
+    gpio.set_input(3, true);
+
+    let mut tx = C9000Transmitter::with_hardware(
+        &Gpio::Mock(gpio.clone()), Box::new(port.clone())
+    ).unwrap();
+    assert_eq!(gpio.transitions_of(0), vec![true]);
+
+    let words: Vec<u32> = (0..25).map(|i| 0x01020300 + i).collect();
+    tx.send(&mut words.clone().into_iter()).unwrap();
+
+    let bytes: Vec<u8> = words.iter()
+        .flat_map(|word| word.to_be_bytes().to_vec())
+        .collect();
+    assert_eq!(port.written(), bytes);
+
+    // Flushed before every block of 10 words and at the end
+    assert_eq!(port.flushes(), 4);
+    assert_eq!(gpio.transitions_of(2), vec![true, false]);
 }
@@ -1,8 +1,8 @@
 use std::thread::sleep;
 use std::time::Duration;
 
-use crate::config::Config;
-use crate::transmitter::Transmitter;
+use crate::config::{Config, ConfigChanges};
+use crate::transmitter::{Capabilities, Result, Transmitter};
 
 pub struct DummyTransmitter;
 
@@ -14,7 +14,7 @@ impl DummyTransmitter {
 }
 
 impl Transmitter for DummyTransmitter {
-    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) {
+    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
         let mut count = 0;
         for word in gen {
             info!("{:032b}", word);
@@ -22,5 +22,17 @@ impl Transmitter for DummyTransmitter {
         }
 
         sleep(Duration::from_millis(count * 3 + 50));
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            baud_rates: vec![512, 1200, 2400],
+            max_power: None
+        }
+    }
+
+    fn reconfigure(&mut self, _: &Config, _: &ConfigChanges) -> bool {
+        true
     }
 }
@@ -0,0 +1,133 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serial::{self, PortSettings, SerialPort, SerialPortSettings};
+
+#[derive(Default)]
+struct State {
+    written: Vec<u8>,
+    settings: Option<PortSettings>,
+    flushes: usize,
+    rts: bool,
+    dtr: bool,
+    fail: bool,
+}
+
+/// Simulated serial port that records the written bytes and never returns
+/// any input. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct MockSerial {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockSerial {
+    pub fn new() -> MockSerial {
+        MockSerial::default()
+    }
+
+    pub fn written(&self) -> Vec<u8> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    pub fn settings(&self) -> Option<PortSettings> {
+        self.state.lock().unwrap().settings
+    }
+
+    pub fn flushes(&self) -> usize {
+        self.state.lock().unwrap().flushes
+    }
+
+    pub fn rts(&self) -> bool {
+        self.state.lock().unwrap().rts
+    }
+
+    pub fn dtr(&self) -> bool {
+        self.state.lock().unwrap().dtr
+    }
+
+    /// Make all following writes fail like a disconnected device.
+    pub fn fail_writes(&self) {
+        self.state.lock().unwrap().fail = true;
+    }
+}
+
+impl Read for MockSerial {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for MockSerial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.fail {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "device removed"));
+        }
+        state.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap().flushes += 1;
+        Ok(())
+    }
+}
+
+impl SerialPort for MockSerial {
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn set_timeout(&mut self, _: Duration) -> serial::Result<()> {
+        Ok(())
+    }
+
+    fn configure(&mut self, settings: &PortSettings) -> serial::Result<()> {
+        self.state.lock().unwrap().settings = Some(*settings);
+        Ok(())
+    }
+
+    fn reconfigure(
+        &mut self,
+        setup: &dyn Fn(&mut dyn SerialPortSettings) -> serial::Result<()>
+    ) -> serial::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut settings = state.settings.unwrap_or(PortSettings {
+            baud_rate: serial::Baud9600,
+            char_size: serial::Bits8,
+            parity: serial::ParityNone,
+            stop_bits: serial::Stop1,
+            flow_control: serial::FlowNone
+        });
+        setup(&mut settings)?;
+        state.settings = Some(settings);
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> serial::Result<()> {
+        self.state.lock().unwrap().rts = level;
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, level: bool) -> serial::Result<()> {
+        self.state.lock().unwrap().dtr = level;
+        Ok(())
+    }
+
+    fn read_cts(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_dsr(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_ri(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_cd(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+}
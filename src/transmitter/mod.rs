@@ -4,6 +4,12 @@ pub mod audio;
 pub mod c9000;
 pub mod rfm69;
 pub mod raspager;
+#[cfg(test)]
+pub mod mock;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
 
 pub use self::audio::AudioTransmitter;
 pub use self::c9000::C9000Transmitter;
@@ -12,35 +18,148 @@ pub use self::ptt::Ptt;
 pub use self::raspager::RaspagerTransmitter;
 pub use self::rfm69::RFM69Transmitter;
 
-use crate::config::{self, Config};
+use raspi::Gpio;
+
+use crate::config::{self, Config, ConfigChanges, GpioBackend, GpioConfig};
+
+// Config sections used by the transmitters
+pub const CONFIG_SECTIONS: [&str; 5] = ["ptt", "raspager", "c9000", "audio", "rfm69"];
+
+#[derive(Debug)]
+pub enum Error {
+    // The hardware could not be opened or set up
+    Init(String),
+    Io(io::Error),
+    PllLock,
+    Audio(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Init(ref reason) => write!(f, "{}", reason),
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::PllLock => write!(f, "PLL locking failed"),
+            Error::Audio(ref reason) => write!(f, "Audio output failed: {}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serial::Error> for Error {
+    fn from(err: serial::Error) -> Error {
+        Error::Io(err.into())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub baud_rates: Vec<usize>,
+    // Highest PA output level that can be configured, if adjustable
+    pub max_power: Option<u8>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        Capabilities {
+            baud_rates: vec![1200],
+            max_power: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status")]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Ok,
+    // Transmissions may fail, e.g. after a PLL lock failure
+    Degraded { reason: String },
+    // The transmitter has to be reinitialized
+    Failed { reason: String },
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::Ok
+    }
+}
 
 pub trait Transmitter {
-    fn send(&mut self, it: &mut dyn Iterator<Item = u32>);
+    fn send(&mut self, it: &mut dyn Iterator<Item = u32>) -> Result<()>;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    fn health(&self) -> Health {
+        Health::Ok
+    }
+
+    /// Release the PTT and power down before the transmitter is dropped.
+    fn shutdown(&mut self) {}
+
+    /// Apply a changed config without reinitializing the hardware. Returns
+    /// false if the transmitter has to be recreated instead.
+    fn reconfigure(&mut self, _config: &Config, _changes: &ConfigChanges) -> bool {
+        false
+    }
 }
 
-pub fn from_config(config: &Config) -> Box<dyn Transmitter> {
-    match config.transmitter {
+pub fn from_config(config: &Config) -> Result<Box<dyn Transmitter>> {
+    Ok(match config.transmitter {
         config::Transmitter::Dummy => {
             Box::new(DummyTransmitter::new(config)) as Box<dyn Transmitter>
         }
         config::Transmitter::Audio => {
-            Box::new(AudioTransmitter::new(config)) as Box<dyn Transmitter>
+            Box::new(AudioTransmitter::new(config)?) as Box<dyn Transmitter>
         }
         config::Transmitter::Raspager => {
             Box::new(
-                RaspagerTransmitter::new(config, raspager::pins::RASPAGER1_PINS)
+                RaspagerTransmitter::new(config, raspager::pins::RASPAGER1_PINS)?
             ) as Box<dyn Transmitter>
         }
         config::Transmitter::Raspager2 => {
             Box::new(
-                RaspagerTransmitter::new(config, raspager::pins::RASPAGER2_PINS)
+                RaspagerTransmitter::new(config, raspager::pins::RASPAGER2_PINS)?
             ) as Box<dyn Transmitter>
         }
         config::Transmitter::C9000 => {
-            Box::new(C9000Transmitter::new(config)) as Box<dyn Transmitter>
+            Box::new(C9000Transmitter::new(config)?) as Box<dyn Transmitter>
         }
         config::Transmitter::RFM69 => {
-            Box::new(RFM69Transmitter::new(config)) as Box<dyn Transmitter>
+            Box::new(RFM69Transmitter::new(config)?) as Box<dyn Transmitter>
+        }
+    })
+}
+
+/// Open the GPIO backend selected in the config. Lines selected by name
+/// require the character device.
+pub fn gpio(config: &GpioConfig) -> Result<Gpio> {
+    let lines: HashMap<usize, String> = config.lines.iter()
+        .filter_map(|(pin, line)| Some((pin.parse().ok()?, line.clone())))
+        .collect();
+
+    let mapped = |gpio: Option<Gpio>| gpio.ok_or_else(|| Error::Init(
+        "Failed to map GPIO. Do you have sufficient permissions to access \
+         GPIO pins?".to_owned()
+    ));
+
+    match config.backend {
+        GpioBackend::Auto if lines.is_empty() => mapped(Gpio::new()),
+        GpioBackend::Memory => mapped(Gpio::memory()),
+        GpioBackend::SysFs => Ok(Gpio::sysfs()),
+        GpioBackend::Auto | GpioBackend::Cdev => {
+            Gpio::cdev(&config.chip, lines).map_err(|err| Error::Init(
+                format!("Failed to open GPIO chip {}: {}", config.chip, err)
+            ))
         }
     }
 }
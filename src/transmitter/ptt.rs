@@ -1,21 +1,40 @@
 use std::ffi::CString;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use raspi::{Direction, Gpio, Model, Pin};
+use raspi::{Direction, Model, Pin};
 use serial;
 
-use crate::config::{PttConfig, PttMethod};
+use crate::config::{GpioConfig, PttConfig, PttMethod};
+use crate::transmitter::{self, Error, Result};
 
-pub enum Ptt {
+// Time `release_all` waits for a PTT that is currently being switched.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    // All open PTTs, so they can be released from outside of the scheduler
+    static ref OPEN: Mutex<Vec<Weak<Mutex<State>>>> = Mutex::new(Vec::new());
+}
+
+#[cfg(test)]
+lazy_static! {
+    // Held by the tests that depend on `release_all` not running meanwhile
+    pub static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+enum Device {
     Gpio {
         pin: Box<dyn Pin>,
         inverted: bool,
     },
     SerialDtr {
-        port: Box<dyn serial::SerialPort>,
+        port: Box<dyn serial::SerialPort + Send>,
         inverted: bool,
     },
     SerialRts {
-        port: Box<dyn serial::SerialPort>,
+        port: Box<dyn serial::SerialPort + Send>,
         inverted: bool,
     },
     #[cfg(hid_ptt)]
@@ -26,37 +45,74 @@ pub enum Ptt {
     },
 }
 
+struct State {
+    device: Device,
+    // Set by `release_all`, the PTT is not keyed again afterwards.
+    released: bool,
+}
+
+/// Output that keys a transmitter. It is shared with `release_all`, which
+/// forces it off if the thread driving the transmitter hangs.
+pub struct Ptt {
+    state: Arc<Mutex<State>>,
+}
+
 impl Ptt {
-    pub fn from_config(config: &PttConfig) -> Ptt {
-        match config.method {
+    /// Open the PTT and make sure it is released.
+    pub fn from_config(config: &PttConfig, gpio: &GpioConfig) -> Result<Ptt> {
+        let mut ptt = Ptt::new(Ptt::open(config, gpio)?);
+        ptt.set(false)?;
+        Ok(ptt)
+    }
+
+    /// Use an output pin that enables the transmitter as PTT.
+    pub fn from_pin(pin: Box<dyn Pin>) -> Ptt {
+        Ptt::new(Device::Gpio { pin, inverted: false })
+    }
+
+    fn new(device: Device) -> Ptt {
+        let state = Arc::new(Mutex::new(State { device, released: false }));
+
+        let mut open = OPEN.lock().unwrap();
+        open.retain(|state| state.strong_count() > 0);
+        open.push(Arc::downgrade(&state));
+
+        Ptt { state }
+    }
+
+    fn open(config: &PttConfig, gpio: &GpioConfig) -> Result<Device> {
+        let open = |port: &str| {
+            serial::open(port).map_err(|err| {
+                Error::Init(format!("Unable to open serial port {}: {}", port, err))
+            })
+        };
+
+        Ok(match config.method {
             PttMethod::Gpio => {
                 info!(
                     "Detected hardware model: {} (GPIOs are only supported on correctly matched hardware)",
                     Model::get());
               
-                let gpio = Gpio::new()
-                    .expect("Failed to map GPIO. Do you have sufficient permissions to access GPIO pins?");
+                let gpio = transmitter::gpio(gpio)?;
 
-                Ptt::Gpio {
-                    pin: gpio.pin(config.gpio_pin, Direction::Output),
+                Device::Gpio {
+                    pin: gpio.pin(config.gpio_pin, Direction::Output)?,
                     inverted: config.inverted,
                 }
             }
             PttMethod::SerialDtr => {
-                let port = serial::open(&*config.serial_port)
-                    .expect("Unable to open serial port");
+                let port = open(&config.serial_port)?;
 
-                Ptt::SerialDtr {
+                Device::SerialDtr {
                     port: Box::new(port),
                     inverted: config.inverted,
                 }
             }
 
             PttMethod::SerialRts => {
-                let port = serial::open(&*config.serial_port)
-                    .expect("Unable to open serial port");
+                let port = open(&config.serial_port)?;
 
-                Ptt::SerialRts {
+                Device::SerialRts {
                     port: Box::new(port),
                     inverted: config.inverted,
                 }
@@ -64,8 +120,9 @@ impl Ptt {
 
             #[cfg(hid_ptt)]
             PttMethod::HidRaw => {
-                let api = hidapi::HidApi::new()
-                    .expect("Unable to initialize HID API");
+                let api = hidapi::HidApi::new().map_err(|err| {
+                    Error::Init(format!("Unable to initialize HID API: {}", err))
+                })?;
                 info!("Using device {}", &*config.hidraw_device);
                 let path = CString::new(&*config.hidraw_device).unwrap();
                 for device in api.devices() {
@@ -79,8 +136,9 @@ impl Ptt {
                     }
                 }
 
-                let cm108device = api.open_path(&path)
-                    .expect("Unable to open HIDraw device");
+                let cm108device = api.open_path(&path).map_err(|err| {
+                    Error::Init(format!("Unable to open HIDraw device: {}", err))
+                })?;
                 let mut string = "Device data: manufacturer \"".to_string();
                 let manufacturer = cm108device.get_manufacturer_string().unwrap();
                 match manufacturer {
@@ -121,54 +179,225 @@ impl Ptt {
                     }
                 }
 
-                Ptt::HidRaw {
+                Device::HidRaw {
                     device: Box::new(cm108device),
                     gpio: pin,
                     inverted: config.inverted,
                 }
             }
+        })
+    }
+
+    pub fn set(&mut self, status: bool) -> Result<()> {
+        let mut state = lock(&self.state);
+        if status && state.released {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "the PTT was released on shutdown"
+            )));
         }
+        state.device.set(status)
     }
 
-    pub fn set(&mut self, status: bool) {
+    /// Key the transmitter until the returned guard is released or dropped.
+    pub fn key(&mut self) -> Result<Keyed<'_>> {
+        self.set(true)?;
+        Ok(Keyed { ptt: self, released: false })
+    }
+}
+
+/// Keeps the PTT on, also releasing it if a transmission is aborted by an
+/// error or panic.
+pub struct Keyed<'a> {
+    ptt: &'a mut Ptt,
+    released: bool,
+}
+
+impl<'a> Keyed<'a> {
+    /// Release the PTT and report whether that failed.
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        self.ptt.set(false)
+    }
+}
+
+impl<'a> Drop for Keyed<'a> {
+    fn drop(&mut self) {
+        if !self.released {
+            if let Err(err) = self.ptt.set(false) {
+                error!("Failed to release the PTT: {}", err);
+            }
+        }
+    }
+}
+
+impl Device {
+    fn set(&mut self, status: bool) -> Result<()> {
         match *self {
-            Ptt::Gpio { ref pin, inverted } => {
+            Device::Gpio { ref pin, inverted } => {
                 pin.set(status != inverted);
             }
-            Ptt::SerialDtr {
+            Device::SerialDtr {
                 ref mut port,
                 inverted
             } => {
-                port.set_dtr(status != inverted).expect(
-                    "Error setting DTR pin"
-                );
+                port.set_dtr(status != inverted)?;
             }
-            Ptt::SerialRts {
+            Device::SerialRts {
                 ref mut port,
                 inverted
             } => {
-                port.set_rts(status != inverted).expect(
-                    "Error setting RTS pin"
-                );
+                port.set_rts(status != inverted)?;
             }
             #[cfg(hid_ptt)]
-            Ptt::HidRaw {
+            Device::HidRaw {
                 ref mut device,
                 gpio,
                 inverted
             } => {
-                if status != inverted {
-                    let buf = [0x00, 0x00, gpio, gpio, 0x00];
-                    device.write(&buf).expect(
-                        "Error writing hidraw interface"
-                    );
+                let buf = if status != inverted {
+                    [0x00, 0x00, gpio, gpio, 0x00]
                 } else {
-                    let buf = [0x00, 0x00, 0x00, gpio, 0x00];
-                    device.write(&buf).expect(
-                        "Error writing hidraw interface"
-                    );
+                    [0x00, 0x00, 0x00, gpio, 0x00]
+                };
+                device.write(&buf).map_err(|err| {
+                    Error::Io(io::Error::new(io::ErrorKind::Other, err.to_string()))
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A PTT stays usable if the thread switching it panicked.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Force all open PTTs off and keep them off, for a scheduler that does not
+/// stop on shutdown. Returns false if a PTT could not be released.
+pub fn release_all() -> bool {
+    let open: Vec<_> = OPEN.lock().unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+
+    let mut released = true;
+    for state in open {
+        let start = Instant::now();
+
+        let mut state = loop {
+            match state.try_lock() {
+                Ok(state) => break Some(state),
+                Err(TryLockError::Poisoned(err)) => break Some(err.into_inner()),
+                Err(TryLockError::WouldBlock) if start.elapsed() < RELEASE_TIMEOUT => {
+                    thread::sleep(Duration::from_millis(10));
                 }
+                Err(TryLockError::WouldBlock) => break None,
             }
+        };
+
+        match state {
+            Some(ref mut state) => {
+                state.released = true;
+                if let Err(err) = state.device.set(false) {
+                    error!("Failed to release the PTT: {}", err);
+                    released = false;
+                }
+            }
+            None => {
+                error!("The PTT is blocked by the transmitter.");
+                released = false;
+            }
+        }
+    }
+    released
+}
+
+// Make sure the transmitter is not left keyed on any exit path.
+impl Drop for Ptt {
+    fn drop(&mut self) {
+        if let Err(err) = self.set(false) {
+            error!("Failed to release the PTT: {}", err);
+        }
+    }
+}
+
+#[test]
+pub fn test_ptt_serial() {
+    use crate::transmitter::mock::MockSerial;
+
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let port = MockSerial::new();
+    let mut ptt = Ptt::new(Device::SerialDtr {
+        port: Box::new(port.clone()),
+        inverted: true,
+    });
+
+    // A transmission that is aborted still releases the PTT.
+    let keyed = ptt.key().unwrap();
+    assert!(!port.dtr());
+    drop(keyed);
+    assert!(port.dtr());
+    ptt.key().unwrap().release().unwrap();
+    assert!(port.dtr());
+
+    ptt.set(true).unwrap();
+    assert!(!port.dtr());
+
+    // The PTT is released when dropped.
+    drop(ptt);
+    assert!(port.dtr());
+    assert!(!port.rts());
+}
+
+#[test]
+pub fn test_ptt_release_hung() {
+    use std::sync::mpsc;
+
+    use crate::shutdown;
+    use crate::transmitter::mock::MockSerial;
+    use crate::transmitter::Transmitter;
+
+    // Keys the PTT and never finishes the transmission.
+    struct HungTransmitter {
+        ptt: Ptt,
+        rx: mpsc::Receiver<()>,
+    }
+
+    impl Transmitter for HungTransmitter {
+        fn send(&mut self, _: &mut dyn Iterator<Item = u32>) -> Result<()> {
+            self.ptt.set(true)?;
+            self.rx.recv().ok();
+            self.ptt.set(true)
         }
     }
+
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let port = MockSerial::new();
+    let (tx, rx) = mpsc::channel();
+    let mut transmitter = HungTransmitter {
+        ptt: Ptt::new(Device::SerialRts {
+            port: Box::new(port.clone()),
+            inverted: false,
+        }),
+        rx,
+    };
+
+    let (result_tx, result_rx) = mpsc::channel();
+    let scheduler = thread::spawn(move || {
+        let result = transmitter.send(&mut vec![0].into_iter());
+        result_tx.send(result.is_ok()).ok();
+    });
+    while !port.rts() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    shutdown::wait(scheduler, Duration::from_millis(100));
+    assert!(!port.rts());
+
+    // The released PTT can not be keyed again.
+    tx.send(()).unwrap();
+    assert_eq!(result_rx.recv(), Ok(false));
+    assert!(!port.rts());
 }
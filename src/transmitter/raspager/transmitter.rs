@@ -1,11 +1,17 @@
-use std::{thread, time};
+use std::{io, thread, time};
 
-use crate::config::Config;
+use crate::config::{Config, ConfigChanges};
+use crate::metrics;
 use raspi::{Direction, Gpio, Model, Pin};
-use crate::transmitter::Transmitter;
+use crate::transmitter::{self, Capabilities, Error, Health, Ptt, Result, Transmitter};
 use crate::transmitter::raspager::adf7012::{Adf7012Config, MuxOut};
 use crate::transmitter::raspager::pins::RaspagerPins;
 
+// Time the ATmega may take to accept the next bit or to finish transmitting
+// its buffer before it is considered to hang.
+const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+const PTT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
 #[inline]
 fn delay_us(micros: u32) {
     thread::sleep(time::Duration::new(0, micros * 1000));
@@ -18,7 +24,8 @@ fn delay_ms(millis: u64) {
 
 pub struct RaspagerTransmitter {
     le: Box<dyn Pin>,
-    ce: Box<dyn Pin>,
+    // Powers the ADF7012, forced off like a PTT if the scheduler hangs
+    ce: Ptt,
     clk: Box<dyn Pin>,
     sdata: Box<dyn Pin>,
     muxout: Box<dyn Pin>,
@@ -27,40 +34,55 @@ pub struct RaspagerTransmitter {
     handshake: Box<dyn Pin>,
     ptt: Box<dyn Pin>,
     config: Adf7012Config,
-    output_level: u8
+    output_level: u8,
+    health: Health
 }
 
 impl RaspagerTransmitter {
-    pub fn new(config: &Config, pins: RaspagerPins) -> RaspagerTransmitter {
+    pub fn new(config: &Config, pins: RaspagerPins) -> Result<RaspagerTransmitter> {
         info!("Initializing RasPager transmitter...");
         info!("Detected {}", Model::get());
-        let gpio = Gpio::new().expect("Failed to map GPIO");
+        let gpio = transmitter::gpio(&config.gpio)?;
+
+        RaspagerTransmitter::with_gpio(config, pins, &gpio)
+    }
 
+    pub fn with_gpio(
+        config: &Config,
+        pins: RaspagerPins,
+        gpio: &Gpio
+    ) -> Result<RaspagerTransmitter> {
         let mut tx = RaspagerTransmitter {
-            le: gpio.pin(pins.le, Direction::Output),
-            ce: gpio.pin(pins.ce, Direction::Output),
-            clk: gpio.pin(pins.clk, Direction::Output),
-            sdata: gpio.pin(pins.sdata, Direction::Output),
-            muxout: gpio.pin(pins.muxout, Direction::Input),
-            atclk: gpio.pin(pins.atclk, Direction::Output),
-            atdata: gpio.pin(pins.atdata, Direction::Output),
-            handshake: gpio.pin(pins.handshake, Direction::Input),
-            ptt: gpio.pin(pins.ptt, Direction::Input),
+            le: gpio.pin(pins.le, Direction::Output)?,
+            ce: Ptt::from_pin(gpio.pin(pins.ce, Direction::Output)?),
+            clk: gpio.pin(pins.clk, Direction::Output)?,
+            sdata: gpio.pin(pins.sdata, Direction::Output)?,
+            muxout: gpio.pin(pins.muxout, Direction::Input)?,
+            atclk: gpio.pin(pins.atclk, Direction::Output)?,
+            atdata: gpio.pin(pins.atdata, Direction::Output)?,
+            handshake: gpio.pin(pins.handshake, Direction::Input)?,
+            ptt: gpio.pin(pins.ptt, Direction::Input)?,
             config: Adf7012Config::new(),
-            output_level: config.raspager.pa_output_level
+            output_level: config.raspager.pa_output_level,
+            health: Health::Ok
         };
 
         tx.reset();
-        tx.config.set_freq_err_correction(config.raspager.freq_corr);
-        tx.config.set_freq(config.raspager.freq);
-        tx.config.set_mod_deviation(config.raspager.mod_deviation);
-        tx.write_config();
+        tx.apply(config);
 
-        tx
+        Ok(tx)
+    }
+
+    fn apply(&mut self, config: &Config) {
+        self.output_level = config.raspager.pa_output_level;
+        self.config.set_freq_err_correction(config.raspager.freq_corr);
+        self.config.set_freq(config.raspager.freq);
+        self.config.set_mod_deviation(config.raspager.mod_deviation);
+        self.write_config();
     }
 
     fn ptt_on(&mut self) -> bool {
-        self.ce.set_high();
+        self.ce.set(true).ok();
         self.config.set_pa_enable(false);
         self.config.set_pa_output_level(0);
         self.config.set_muxout(MuxOut::RegReady);
@@ -76,6 +98,7 @@ impl RaspagerTransmitter {
                 true
             } else {
                 error!("PLL locking failed");
+                metrics::pll_lock_failure();
                 self.ptt_off();
                 false
             }
@@ -86,9 +109,8 @@ impl RaspagerTransmitter {
     }
 
     fn ptt_off(&mut self) {
-        while self.ptt.read() {
-            debug!("PTT still high");
-            delay_ms(100);
+        if !self.ptt.wait_for(false, PTT_TIMEOUT) {
+            warn!("PTT of the ATmega still high, powering down anyway.");
         }
 
         self.config.set_pa_enable(false);
@@ -96,7 +118,23 @@ impl RaspagerTransmitter {
         self.write_config();
 
         delay_ms(100);
-        self.ce.set_low();
+        self.ce.set(false).ok();
+    }
+
+    // Power down after the ATmega stopped responding.
+    fn fail(&mut self, reason: &str) -> Error {
+        self.atdata.set_low();
+        self.power_down();
+        self.health = Health::Failed { reason: reason.to_owned() };
+        Error::Io(io::Error::new(io::ErrorKind::TimedOut, reason))
+    }
+
+    // Turn off the power amplifier without waiting for the PTT line.
+    fn power_down(&mut self) {
+        self.config.set_pa_enable(false);
+        self.config.set_pa_output_level(0);
+        self.write_config();
+        self.ce.set(false).ok();
     }
 
     fn lock_pll(&mut self) -> bool {
@@ -169,18 +207,18 @@ impl RaspagerTransmitter {
     }
 
     fn reset(&mut self) {
-        self.ce.set_low();
+        self.ce.set(false).ok();
         self.le.set_high();
         self.clk.set_high();
         self.sdata.set_high();
         delay_ms(5);
-        self.ce.set_high();
+        self.ce.set(true).ok();
         delay_ms(100);
     }
 }
 
 impl Transmitter for RaspagerTransmitter {
-    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) {
+    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
         // try multiple times until the PLL is locked
         let mut pll_locked = false;
         for _ in 0..5 {
@@ -191,17 +229,21 @@ impl Transmitter for RaspagerTransmitter {
         }
 
         if !pll_locked {
-            error!("Could not transmit message: PLL locking failed");
             self.ptt_off();
             delay_ms(200);
-            return;
+            self.health = Health::Degraded {
+                reason: Error::PllLock.to_string()
+            };
+            return Err(Error::PllLock);
         }
 
         for word in gen {
             for i in (0..32).rev() {
-                while !self.handshake.read() {
+                if !self.handshake.read() {
                     debug!("ATmega Buffer full");
-                    delay_us(100);
+                    if !self.handshake.wait_for(true, HANDSHAKE_TIMEOUT) {
+                        return Err(self.fail("ATmega does not accept data"));
+                    }
                 }
 
                 let bit = (word & (1 << i)) != 0;
@@ -219,5 +261,126 @@ impl Transmitter for RaspagerTransmitter {
 
         self.ptt_off();
         delay_ms(200);
+
+        self.health = Health::Ok;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            baud_rates: vec![1200],
+            max_power: Some(63)
+        }
+    }
+
+    fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    fn shutdown(&mut self) {
+        self.atdata.set_low();
+        self.power_down();
+    }
+
+    // The registers are written without a reset, the PLL is locked again on
+    // the next transmission anyway.
+    fn reconfigure(&mut self, config: &Config, changes: &ConfigChanges) -> bool {
+        if changes.contains("raspager") {
+            self.apply(config);
+        }
+        true
+    }
+}
+
+impl Drop for RaspagerTransmitter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// Registers shifted into the ADF7012, each latched by a rising LE.
+#[cfg(test)]
+fn written_registers(gpio: &raspi::MockGpio, pins: &RaspagerPins) -> Vec<u32> {
+    let mut registers = Vec::new();
+    let (mut sdata, mut value) = (false, 0u32);
+
+    for (pin, level) in gpio.transitions() {
+        if pin == pins.sdata {
+            sdata = level;
+        } else if pin == pins.clk && level {
+            value = (value << 1) | sdata as u32;
+        } else if pin == pins.le {
+            if level {
+                registers.push(value);
+            }
+            value = 0;
+        }
     }
+
+    registers
+}
+
+#[test]
+pub fn test_raspager_registers() {
+    use crate::transmitter::raspager::pins::RASPAGER2_PINS;
+
+    let _lock = transmitter::ptt::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let gpio = raspi::MockGpio::new();
+    let config = Config::default();
+    let _tx = RaspagerTransmitter::with_gpio(
+        &config, RASPAGER2_PINS, &Gpio::Mock(gpio.clone())
+    ).unwrap();
+
+    let mut adf = Adf7012Config::new();
+    adf.set_freq_err_correction(config.raspager.freq_corr);
+    adf.set_freq(config.raspager.freq);
+    adf.set_mod_deviation(config.raspager.mod_deviation);
+
+    // Skip the latch of the reset.
+    let registers = written_registers(&gpio, &RASPAGER2_PINS);
+    assert_eq!(registers[1..], [adf.r0(), adf.r1(), adf.r2(), adf.r3()]);
+}
+
+#[test]
+pub fn test_raspager_send() {
+    use crate::transmitter::raspager::pins::RASPAGER2_PINS;
+
+    let _lock = transmitter::ptt::TEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let pins = RASPAGER2_PINS;
+    let gpio = raspi::MockGpio::new();
+    let mut tx = RaspagerTransmitter::with_gpio(
+        &Config::default(), RASPAGER2_PINS, &Gpio::Mock(gpio.clone())
+    ).unwrap();
+
+    // The ADF7012 is not ready, so the PLL is never locked.
+    assert!(matches!(tx.send(&mut vec![0].into_iter()), Err(Error::PllLock)));
+    assert!(matches!(tx.health(), Health::Degraded { .. }));
+
+    gpio.set_input(pins.muxout, true);
+    gpio.set_input(pins.handshake, true);
+    gpio.clear();
+
+    tx.send(&mut vec![0xdeadbeef].into_iter()).unwrap();
+    assert_eq!(tx.health(), Health::Ok);
+
+    // Bits are clocked into the ATmega on the rising edge of ATCLK.
+    let mut data = false;
+    let mut word = 0u32;
+    for (pin, level) in gpio.transitions() {
+        if pin == pins.atdata {
+            data = level;
+        } else if pin == pins.atclk && level {
+            word = (word << 1) | data as u32;
+        }
+    }
+    assert_eq!(word, 0xdeadbeef);
+
+    let ce = gpio.transitions_of(pins.ce);
+    assert_eq!((ce[0], ce[ce.len() - 1]), (true, false));
+
+    // A full buffer that is never emptied aborts the transmission.
+    gpio.set_input(pins.handshake, false);
+    assert!(tx.send(&mut vec![0xdeadbeef].into_iter()).is_err());
+    assert!(matches!(tx.health(), Health::Failed { .. }));
+    assert_eq!(gpio.transitions_of(pins.ce).last(), Some(&false));
 }
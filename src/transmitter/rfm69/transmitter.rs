@@ -1,20 +1,26 @@
 use serial::{self, SerialPort};
 
-use crate::config::Config;
-use crate::transmitter::Transmitter;
+use crate::config::{Config, ConfigChanges};
+use crate::transmitter::{Error, Health, Result, Transmitter};
 
 pub struct RFM69Transmitter {
-    serial: Box<dyn serial::SerialPort>
+    serial: Box<dyn serial::SerialPort>,
+    health: Health
 }
 
 impl RFM69Transmitter {
-    pub fn new(config: &Config) -> RFM69Transmitter {
+    pub fn new(config: &Config) -> Result<RFM69Transmitter> {
         info!("Initializing RFM69 transmitter...");
 
-        let mut serial = serial::open(&config.rfm69.port).expect(
-            "Unable to open serial port"
-        );
+        let serial = serial::open(&config.rfm69.port).map_err(|err| {
+            Error::Init(format!("Unable to open serial port {}: {}",
+                                config.rfm69.port, err))
+        })?;
 
+        RFM69Transmitter::with_port(Box::new(serial))
+    }
+
+    pub fn with_port(mut serial: Box<dyn SerialPort>) -> Result<RFM69Transmitter> {
         serial
             .configure(&serial::PortSettings {
                 baud_rate: serial::BaudRate::Baud38400,
@@ -23,14 +29,17 @@ impl RFM69Transmitter {
                 stop_bits: serial::StopBits::Stop1,
                 flow_control: serial::FlowControl::FlowNone
             })
-            .expect("Unable to configure serial port");
+            .map_err(|err| {
+                Error::Init(format!("Unable to configure serial port: {}", err))
+            })?;
 
-        RFM69Transmitter { serial: Box::new(serial) }
+        Ok(RFM69Transmitter {
+            serial,
+            health: Health::Ok
+        })
     }
-}
 
-impl Transmitter for RFM69Transmitter {
-    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) {
+    fn write(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
         for word in gen {
             let bytes = [
                 ((word & 0xff000000) >> 24) as u8,
@@ -39,21 +48,52 @@ impl Transmitter for RFM69Transmitter {
                 (word & 0x000000ff) as u8,
             ];
 
-            if (*self.serial).write_all(&bytes).is_err() {
-                error!("Unable to write data to the serial port");
-                return;
-            }
+            (*self.serial).write_all(&bytes)?;
         }
 
         // Send End of Transmission packet
         let eot = [0x17 as u8];
-        if (*self.serial).write_all(&eot).is_err() {
-            error!("Unable to send end of transmission byte");
-            return;
-        }
+        (*self.serial).write_all(&eot)?;
+        (*self.serial).flush()?;
+        Ok(())
+    }
+}
 
-        if (*self.serial).flush().is_err() {
-            error!("Unable to flush serial port");
-        }
+impl Transmitter for RFM69Transmitter {
+    fn send(&mut self, gen: &mut dyn Iterator<Item = u32>) -> Result<()> {
+        let result = self.write(gen);
+
+        self.health = match result {
+            Ok(()) => Health::Ok,
+            Err(ref err) => Health::Failed { reason: err.to_string() }
+        };
+        result
     }
+
+    fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    fn reconfigure(&mut self, _: &Config, changes: &ConfigChanges) -> bool {
+        !changes.contains("rfm69")
+    }
+}
+
+#[test]
+pub fn test_rfm69_framing() {
+    use crate::transmitter::mock::MockSerial;
+
+    let port = MockSerial::new();
+    let mut tx = RFM69Transmitter::with_port(Box::new(port.clone())).unwrap();
+    assert_eq!(port.settings().unwrap().baud_rate, serial::Baud38400);
+
+    tx.send(&mut vec![0x12345678, 0x7cd215d8].into_iter()).unwrap();
+    assert_eq!(port.written(), vec![
+        0x12, 0x34, 0x56, 0x78, 0x7c, 0xd2, 0x15, 0xd8, 0x17
+    ]);
+    assert_eq!(tx.health(), Health::Ok);
+
+    port.fail_writes();
+    assert!(tx.send(&mut vec![0x12345678].into_iter()).is_err());
+    assert!(matches!(tx.health(), Health::Failed { .. }));
 }